@@ -0,0 +1,81 @@
+//! Times the PATH index listing every command, as completing an empty
+//! command name does: with a fresh index, which reads every directory, and
+//! with one whose listings are kept, against a plain scan of the directories
+//! that keeps nothing. Run with
+//! `cargo bench --bench path_index [dirs] [files per dir]`.
+
+#[allow(dead_code)]
+#[path = "../src/path_index.rs"]
+mod path_index;
+
+use std::collections::HashSet;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use path_index::PathIndex;
+
+const ROUNDS: u32 = 20;
+
+/// Make `dirs` directories of `files` executables each under a fresh
+/// temporary directory, and return their paths.
+fn make_tree(dirs: usize, files: usize) -> (PathBuf, Vec<String>) {
+	let root = std::env::temp_dir().join(format!("myshell-bench-{}", std::process::id()));
+	let mut paths = Vec::new();
+	for d in 0..dirs {
+		let dir = root.join(format!("bin{d}"));
+		fs::create_dir_all(&dir).unwrap();
+		for f in 0..files {
+			let file = dir.join(format!("cmd{d}-{f}"));
+			fs::write(&file, "").unwrap();
+			fs::set_permissions(&file, fs::Permissions::from_mode(0o755)).unwrap();
+		}
+		paths.push(dir.to_string_lossy().into_owned());
+	}
+	(root, paths)
+}
+
+/// The commands in `dirs`, read without an index, as a baseline.
+fn plain_scan(dirs: &[String]) -> HashSet<String> {
+	let mut names = HashSet::new();
+	for dir in dirs {
+		for entry in fs::read_dir(dir).into_iter().flatten().filter_map(Result::ok) {
+			if path_index::is_executable(&entry.path()) {
+				names.extend(entry.file_name().into_string().ok());
+			}
+		}
+	}
+	names
+}
+
+/// The mean time `run` takes over `ROUNDS` runs.
+fn time(mut run: impl FnMut() -> usize) -> (Duration, usize) {
+	let mut found = run(); // Warm the file system caches first
+	let start = Instant::now();
+	for _ in 0..ROUNDS {
+		found = run();
+	}
+	(start.elapsed() / ROUNDS, found)
+}
+
+fn main() {
+	let mut args = std::env::args().skip(1).filter(|arg| arg != "--bench");
+	let dirs = args.next().and_then(|n| n.parse().ok()).unwrap_or(40);
+	let files = args.next().and_then(|n| n.parse().ok()).unwrap_or(500);
+	let (root, paths) = make_tree(dirs, files);
+	// Listings of directories changed in the last second aren't kept
+	std::thread::sleep(Duration::from_millis(1100));
+
+	let (baseline, expected) = time(|| plain_scan(&paths).len());
+	let (cold, found) = time(|| PathIndex::new(paths.clone()).names("").len());
+	assert_eq!(found, expected);
+	let index = PathIndex::new(paths.clone());
+	let (warm, _) = time(|| index.names("").len());
+
+	println!("{dirs} directories of {files} commands");
+	println!("plain scan:             {baseline:>10.2?}");
+	println!("index, every dir read:  {cold:>10.2?}");
+	println!("index, listings kept:   {warm:>10.2?}");
+	fs::remove_dir_all(Path::new(&root)).unwrap();
+}
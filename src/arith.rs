@@ -0,0 +1,191 @@
+//! Integer expression evaluator for `$(( ... ))`.
+//! Supports the usual C operators on i64, parentheses, and variable names,
+//! which evaluate to their value (0 when unset or empty).
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+	Num(i64),
+	Name(String),
+	Op(&'static str),
+	LParen,
+	RParen,
+}
+
+// Longest operators first so `<=` is not read as `<` followed by `=`
+const OPERATORS: [&str; 20] = [
+	"<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+	"+", "-", "*", "/", "%", "<", ">", "!", "~", "&", "|", "^",
+];
+
+/// How many variable values may themselves be expressions referring to
+/// other variables before we give up, e.g. `a=b b=a`.
+const MAX_DEPTH: usize = 32;
+
+fn lex(expr: &str) -> Result<Vec<Tok>, String> {
+	let mut toks = Vec::new();
+	let mut rest = expr.trim_start();
+
+	while let Some(ch) = rest.chars().next() {
+		if ch.is_ascii_digit() {
+			let len = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+			toks.push(Tok::Num(parse_number(&rest[..len])?));
+			rest = &rest[len..];
+		} else if ch.is_ascii_alphabetic() || ch == '_' {
+			let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+			toks.push(Tok::Name(rest[..len].to_owned()));
+			rest = &rest[len..];
+		} else if ch == '(' || ch == ')' {
+			toks.push(if ch == '(' { Tok::LParen } else { Tok::RParen });
+			rest = &rest[1..];
+		} else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+			toks.push(Tok::Op(op));
+			rest = &rest[op.len()..];
+		} else {
+			return Err(format!("syntax error: invalid arithmetic operator (error token is \"{rest}\")"));
+		}
+		rest = rest.trim_start();
+	}
+
+	Ok(toks)
+}
+
+fn parse_number(text: &str) -> Result<i64, String> {
+	let parsed = if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+		i64::from_str_radix(hex, 16)
+	} else if text.len() > 1 && text.starts_with('0') {
+		i64::from_str_radix(&text[1..], 8)
+	} else {
+		text.parse()
+	};
+	parsed.map_err(|_| format!("{text}: value too great for base (error token is \"{text}\")"))
+}
+
+/// Binding strength of a binary operator, higher binds tighter.
+fn precedence(op: &str) -> Option<u8> {
+	Some(match op {
+		"||" => 1,
+		"&&" => 2,
+		"|" => 3,
+		"^" => 4,
+		"&" => 5,
+		"==" | "!=" => 6,
+		"<" | "<=" | ">" | ">=" => 7,
+		"<<" | ">>" => 8,
+		"+" | "-" => 9,
+		"*" | "/" | "%" => 10,
+		_ => return None,
+	})
+}
+
+struct Parser<'a, F: Fn(&str) -> Option<String>> {
+	toks: Vec<Tok>,
+	pos: usize,
+	lookup: &'a F,
+	depth: usize,
+}
+
+impl<F: Fn(&str) -> Option<String>> Parser<'_, F> {
+	fn peek(&self) -> Option<&Tok> {
+		self.toks.get(self.pos)
+	}
+
+	fn next(&mut self) -> Option<Tok> {
+		let tok = self.toks.get(self.pos).cloned();
+		self.pos += 1;
+		tok
+	}
+
+	fn expr(&mut self, min_prec: u8) -> Result<i64, String> {
+		let mut lhs = self.unary()?;
+
+		while let Some(Tok::Op(op)) = self.peek() {
+			let op = *op;
+			let Some(prec) = precedence(op).filter(|&p| p >= min_prec) else {
+				break;
+			};
+			self.pos += 1;
+			let rhs = self.expr(prec + 1)?;
+			lhs = apply(op, lhs, rhs)?;
+		}
+
+		Ok(lhs)
+	}
+
+	fn unary(&mut self) -> Result<i64, String> {
+		match self.next() {
+			Some(Tok::Op("-")) => Ok(self.unary()?.wrapping_neg()),
+			Some(Tok::Op("+")) => self.unary(),
+			Some(Tok::Op("!")) => Ok((self.unary()? == 0) as i64),
+			Some(Tok::Op("~")) => Ok(!self.unary()?),
+			Some(Tok::Num(n)) => Ok(n),
+			Some(Tok::Name(name)) => self.variable(&name),
+			Some(Tok::LParen) => {
+				let value = self.expr(0)?;
+				match self.next() {
+					Some(Tok::RParen) => Ok(value),
+					_ => Err("missing `)'".to_owned()),
+				}
+			},
+			_ => Err("syntax error: operand expected".to_owned()),
+		}
+	}
+
+	/// A variable's value may itself be an expression, as in bash.
+	fn variable(&mut self, name: &str) -> Result<i64, String> {
+		let value = (self.lookup)(name).unwrap_or_default();
+		if value.trim().is_empty() {
+			return Ok(0);
+		}
+		if let Ok(n) = value.trim().parse() {
+			return Ok(n);
+		}
+		if self.depth >= MAX_DEPTH {
+			return Err(format!("{name}: expression recursion level exceeded"));
+		}
+		eval_at_depth(&value, self.lookup, self.depth + 1)
+	}
+}
+
+fn apply(op: &str, lhs: i64, rhs: i64) -> Result<i64, String> {
+	Ok(match op {
+		"+" => lhs.wrapping_add(rhs),
+		"-" => lhs.wrapping_sub(rhs),
+		"*" => lhs.wrapping_mul(rhs),
+		"/" | "%" if rhs == 0 => return Err("division by 0".to_owned()),
+		"/" => lhs.wrapping_div(rhs),
+		"%" => lhs.wrapping_rem(rhs),
+		"<<" => lhs.wrapping_shl(rhs as u32),
+		">>" => lhs.wrapping_shr(rhs as u32),
+		"<" => (lhs < rhs) as i64,
+		"<=" => (lhs <= rhs) as i64,
+		">" => (lhs > rhs) as i64,
+		">=" => (lhs >= rhs) as i64,
+		"==" => (lhs == rhs) as i64,
+		"!=" => (lhs != rhs) as i64,
+		"&" => lhs & rhs,
+		"^" => lhs ^ rhs,
+		"|" => lhs | rhs,
+		"&&" => (lhs != 0 && rhs != 0) as i64,
+		"||" => (lhs != 0 || rhs != 0) as i64,
+		_ => unreachable!("not a binary operator: {op}"),
+	})
+}
+
+fn eval_at_depth<F: Fn(&str) -> Option<String>>(expr: &str, lookup: &F, depth: usize) -> Result<i64, String> {
+	let toks = lex(expr)?;
+	if toks.is_empty() {
+		return Ok(0);
+	}
+
+	let mut parser = Parser { toks, pos: 0, lookup, depth };
+	let value = parser.expr(0)?;
+	match parser.peek() {
+		None => Ok(value),
+		Some(_) => Err(format!("{expr}: syntax error in expression")),
+	}
+}
+
+/// Evaluate an arithmetic expression, resolving names through `lookup`.
+pub fn evaluate<F: Fn(&str) -> Option<String>>(expr: &str, lookup: &F) -> Result<i64, String> {
+	eval_at_depth(expr, lookup, 0)
+}
@@ -0,0 +1,39 @@
+//! The shell's own command-line arguments.
+
+/// What the shell was started to do.
+pub enum Invocation {
+	/// Read commands from stdin, at a prompt when it is a terminal
+	Stdin,
+	/// Run a script file, as in `myshell script.sh`
+	Script(String),
+	/// Run a command string, as in `myshell -c 'echo hi && pwd'`
+	Command(String),
+}
+
+/// The parsed command line.
+pub struct Args {
+	pub invocation: Invocation,
+	pub name: Option<String>, // `$0`: the script, or the word after a `-c` command
+	pub positional: Vec<String>, // `$1` onwards
+}
+
+/// Work out the invocation from the arguments after the program name.
+/// Options come first; `--` ends them, and the first operand is a script.
+/// Whatever follows the script or `-c` command sets `$0` and the positional parameters.
+pub fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+	let invocation = match args.next() {
+		None => Invocation::Stdin,
+		Some(arg) => match arg.as_str() {
+			"-c" => Invocation::Command(args.next().ok_or("-c: option requires an argument")?),
+			"-" | "--" => args.next().map_or(Invocation::Stdin, Invocation::Script),
+			option if option.starts_with('-') => return Err(format!("{option}: invalid option")),
+			_ => Invocation::Script(arg),
+		},
+	};
+	let name = match &invocation {
+		Invocation::Script(path) => Some(path.clone()),
+		Invocation::Command(_) => args.next(),
+		Invocation::Stdin => None,
+	};
+	Ok(Args { invocation, name, positional: args.collect() })
+}
@@ -0,0 +1,127 @@
+//! Tab completion for the line editor.
+
+use std::{env, fs};
+
+use crate::expand::quote;
+use crate::{capture_output, ShellState, BUILTIN_COMMANDS};
+
+/// How to complete the arguments of a command, as set up by `complete`.
+#[derive(Clone)]
+pub enum CompletionSpec {
+	WordList(String), // -W: candidates are the words of this list
+	Command(String), // -C: candidates are the lines this command prints
+}
+
+impl CompletionSpec {
+	/// The `complete` invocation that would register this spec for `name`.
+	pub fn describe(&self, name: &str) -> String {
+		match self {
+			CompletionSpec::WordList(words) => format!("complete -W {} {name}", quote(words)),
+			CompletionSpec::Command(command) => format!("complete -C {} {name}", quote(command)),
+		}
+	}
+}
+
+/// Candidates from a registered spec. A `-C` command is run like bash runs
+/// it, with the command name, the word being completed and the word before it.
+fn spec_candidates(spec: &CompletionSpec, command: &str, word: &str, previous: &str, shell: &ShellState) -> Vec<String> {
+	let candidates: Vec<String> = match spec {
+		CompletionSpec::WordList(words) => words.split_whitespace().map(str::to_owned).collect(),
+		CompletionSpec::Command(program) => {
+			let source = format!("{program} {} {} {}", quote(command), quote(word), quote(previous));
+			capture_output(&source, shell).lines().map(str::to_owned).collect()
+		},
+	};
+	candidates.into_iter().filter(|c| c.starts_with(word)).collect()
+}
+
+/// Command names from builtins, functions and the PATH index that start with `prefix`.
+fn command_names(prefix: &str, shell: &ShellState) -> Vec<String> {
+	let path_names = shell.path_index.names(prefix);
+	BUILTIN_COMMANDS
+		.iter()
+		.copied()
+		.chain(shell.functions.keys().map(String::as_str))
+		.chain(path_names.iter().map(String::as_str))
+		.filter(|name| name.starts_with(prefix))
+		.map(str::to_owned)
+		.collect()
+}
+
+/// Paths completing `word`, looked up in the directory it names, relative to
+/// `base` (the current directory if empty), with a leading `~` standing for
+/// HOME. Directories get a trailing `/`, and dotfiles are only offered when
+/// asked for.
+fn file_names(word: &str, base: &str, shell: &ShellState) -> Vec<String> {
+	let (dir, prefix) = match word.rfind('/') {
+		Some(slash) => word.split_at(slash + 1),
+		None => ("", word),
+	};
+	let search_dir = match dir.strip_prefix('~') {
+		Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("{}{rest}", shell.vars.get("HOME").unwrap_or("")),
+		_ if dir.starts_with('/') => dir.to_owned(),
+		_ if base.is_empty() && dir.is_empty() => ".".to_owned(),
+		_ if base.is_empty() => dir.to_owned(),
+		_ => format!("{base}/{dir}"),
+	};
+
+	let Ok(entries) = fs::read_dir(search_dir) else {
+		return Vec::new();
+	};
+	entries
+		.filter_map(Result::ok)
+		.filter_map(|entry| {
+			let name = entry.file_name().into_string().ok()?;
+			if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+				return None;
+			}
+			// Follow symlinks, so a link to a directory completes like one
+			let is_dir = entry.path().metadata().is_ok_and(|m| m.is_dir());
+			Some(format!("{dir}{name}{}", if is_dir { "/" } else { "" }))
+		})
+		.collect()
+}
+
+/// Directories `cd` could go to: those under the current directory and,
+/// for a relative name, under each CDPATH entry.
+fn directory_names(word: &str, shell: &ShellState) -> Vec<String> {
+	let mut names = file_names(word, "", shell);
+	if !word.starts_with(['/', '.', '~']) {
+		for base in env::split_paths(shell.vars.get("CDPATH").unwrap_or("")).filter(|b| !b.as_os_str().is_empty()) {
+			names.extend(file_names(word, &base.to_string_lossy(), shell));
+		}
+	}
+	names.retain(|name| name.ends_with('/'));
+	names
+}
+
+/// Candidates for the word that ends at the end of `before_cursor`, along
+/// with where that word starts, in chars. The command name is completed from
+/// builtins and PATH, unless it is a path. Arguments use the command's spec
+/// from `complete` if it has one that matches; otherwise arguments to `cd` are
+/// directories and other words are file names.
+pub fn complete(before_cursor: &[char], shell: &ShellState) -> (usize, Vec<String>) {
+	let start = before_cursor.iter().rposition(|c| c.is_whitespace()).map_or(0, |i| i + 1);
+	let word: String = before_cursor[start..].iter().collect();
+	let line: String = before_cursor[..start].iter().collect();
+	let command = line.split_whitespace().next();
+
+	if let Some(spec) = command.and_then(|c| shell.completions.get(c)) {
+		let previous = line.split_whitespace().last().unwrap_or("");
+		let mut candidates = spec_candidates(spec, command.unwrap(), &word, previous, shell);
+		if !candidates.is_empty() {
+			candidates.sort();
+			candidates.dedup();
+			return (start, candidates);
+		}
+	}
+
+	let mut candidates = match command {
+		None if !word.contains('/') => command_names(&word, shell),
+		Some("cd") => directory_names(&word, shell),
+		_ => file_names(&word, "", shell),
+	};
+	candidates.sort();
+	candidates.dedup();
+	(start, candidates)
+}
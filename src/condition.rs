@@ -0,0 +1,338 @@
+//! The expressions of `test` and `[`: file checks like `-f path`, string and
+//! integer comparisons, combined with `!`, `-a`, `-o` and parentheses. Also
+//! those of `[[ ... ]]`, which are parsed with the command and expanded as
+//! they are evaluated.
+
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::Path;
+
+use crate::{expand, sys, ShellState};
+
+const UNARY_OPERATORS: [&str; 21] = [
+	"-z", "-n", "-e", "-f", "-d", "-h", "-L", "-s", "-b", "-c", "-p",
+	"-S", "-g", "-u", "-k", "-r", "-w", "-x", "-O", "-G", "-t",
+];
+
+const BINARY_OPERATORS: [&str; 14] = [
+	"=", "==", "!=", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+];
+
+/// Whether `op` is a test on one operand, like `-f`.
+pub fn is_unary_operator(op: &str) -> bool {
+	UNARY_OPERATORS.contains(&op)
+}
+
+/// Whether `op` compares two operands, like `=` or `-lt`.
+pub fn is_binary_operator(op: &str) -> bool {
+	BINARY_OPERATORS.contains(&op)
+}
+
+/// Evaluate the arguments of `test`, or of `[` without its closing `]`.
+/// No arguments are false, and a single one is true when it isn't empty.
+pub fn test(args: &[&str]) -> Result<bool, String> {
+	if args.is_empty() {
+		return Ok(false);
+	}
+	let mut parser = Parser { args, pos: 0 };
+	let result = parser.or()?;
+	match parser.peek(0) {
+		None => Ok(result),
+		Some(_) => Err("too many arguments".to_owned()),
+	}
+}
+
+/// Recursive descent over the arguments. `-o` binds looser than `-a`, which
+/// binds looser than `!`.
+struct Parser<'a> {
+	args: &'a [&'a str],
+	pos: usize,
+}
+
+impl<'a> Parser<'a> {
+	fn peek(&self, ahead: usize) -> Option<&'a str> {
+		self.args.get(self.pos + ahead).copied()
+	}
+
+	fn or(&mut self) -> Result<bool, String> {
+		let mut result = self.and()?;
+		while self.peek(0) == Some("-o") {
+			self.pos += 1;
+			result |= self.and()?;
+		}
+		Ok(result)
+	}
+
+	fn and(&mut self) -> Result<bool, String> {
+		let mut result = self.not()?;
+		while self.peek(0) == Some("-a") {
+			self.pos += 1;
+			result &= self.not()?;
+		}
+		Ok(result)
+	}
+
+	fn not(&mut self) -> Result<bool, String> {
+		// A `!` on its own, or on the left of a comparison as in `! = x`, is just a string
+		let comparison = self.peek(1).is_some_and(is_binary_operator) && self.peek(2).is_some();
+		if self.peek(0) == Some("!") && self.peek(1).is_some() && !comparison {
+			self.pos += 1;
+			return Ok(!self.not()?);
+		}
+		self.primary()
+	}
+
+	fn primary(&mut self) -> Result<bool, String> {
+		let Some(first) = self.peek(0) else {
+			return Err("argument expected".to_owned());
+		};
+		if let (Some(op), Some(right)) = (self.peek(1), self.peek(2)) {
+			if is_binary_operator(op) {
+				self.pos += 3;
+				return binary(first, op, right);
+			}
+		}
+		if first == "(" && self.peek(1).is_some() {
+			self.pos += 1;
+			let result = self.or()?;
+			if self.peek(0) != Some(")") {
+				return Err("`)' expected".to_owned());
+			}
+			self.pos += 1;
+			return Ok(result);
+		}
+		if is_unary_operator(first) {
+			if let Some(operand) = self.peek(1) {
+				self.pos += 2;
+				return Ok(unary(first, operand));
+			}
+		}
+		// A lone operator like `-n` is just a non-empty string
+		self.pos += 1;
+		Ok(!first.is_empty())
+	}
+}
+
+/// Apply a test on one operand, e.g. `-d` to a path or `-z` to a string.
+pub fn unary(op: &str, operand: &str) -> bool {
+	let path = Path::new(operand);
+	let meta = fs::metadata(path).ok();
+	let is = |check: fn(&fs::Metadata) -> bool| meta.as_ref().is_some_and(check);
+	let (uid, gid) = sys::effective_ids();
+	match op {
+		"-z" => operand.is_empty(),
+		"-n" => !operand.is_empty(),
+		"-e" => meta.is_some(),
+		"-f" => is(|m| m.is_file()),
+		"-d" => is(|m| m.is_dir()),
+		"-h" | "-L" => fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink()),
+		"-s" => is(|m| m.len() > 0),
+		"-b" => is(|m| m.file_type().is_block_device()),
+		"-c" => is(|m| m.file_type().is_char_device()),
+		"-p" => is(|m| m.file_type().is_fifo()),
+		"-S" => is(|m| m.file_type().is_socket()),
+		"-u" => is(|m| m.mode() & 0o4000 != 0),
+		"-g" => is(|m| m.mode() & 0o2000 != 0),
+		"-k" => is(|m| m.mode() & 0o1000 != 0),
+		"-r" => sys::can_access(path, sys::R_OK),
+		"-w" => sys::can_access(path, sys::W_OK),
+		"-x" => sys::can_access(path, sys::X_OK),
+		"-O" => meta.is_some_and(|m| m.uid() == uid),
+		"-G" => meta.is_some_and(|m| m.gid() == gid),
+		"-t" => operand.parse().is_ok_and(sys::is_terminal),
+		_ => false,
+	}
+}
+
+/// Compare two operands: strings with `=`, `!=`, `<` and `>`, integers with
+/// `-eq` and friends, and files with `-nt` (newer than), `-ot` (older than)
+/// and `-ef` (the same file).
+pub fn binary(left: &str, op: &str, right: &str) -> Result<bool, String> {
+	let modified = |path: &str| fs::metadata(path).and_then(|m| m.modified()).ok();
+	Ok(match op {
+		"=" | "==" => left == right,
+		"!=" => left != right,
+		"<" => left < right,
+		">" => left > right,
+		"-eq" => integer(left)? == integer(right)?,
+		"-ne" => integer(left)? != integer(right)?,
+		"-lt" => integer(left)? < integer(right)?,
+		"-le" => integer(left)? <= integer(right)?,
+		"-gt" => integer(left)? > integer(right)?,
+		"-ge" => integer(left)? >= integer(right)?,
+		// A file that exists is newer than one that doesn't
+		"-nt" => match (modified(left), modified(right)) {
+			(Some(left), Some(right)) => left > right,
+			(left, _) => left.is_some(),
+		},
+		"-ot" => match (modified(left), modified(right)) {
+			(Some(left), Some(right)) => left < right,
+			(_, right) => right.is_some(),
+		},
+		"-ef" => match (fs::metadata(left), fs::metadata(right)) {
+			(Ok(left), Ok(right)) => left.dev() == right.dev() && left.ino() == right.ino(),
+			_ => false,
+		},
+		_ => return Err(format!("{op}: binary operator expected")),
+	})
+}
+
+fn integer(text: &str) -> Result<i64, String> {
+	text.trim().parse().map_err(|_| format!("{text}: integer expression expected"))
+}
+
+/// The expression inside `[[ ... ]]`, with its operands still raw words.
+#[derive(Debug, Clone)]
+pub enum Conditional {
+	Not(Box<Conditional>),
+	And(Box<Conditional>, Box<Conditional>),
+	Or(Box<Conditional>, Box<Conditional>),
+	Unary(String, String), // Operator and operand, as in `-f path`
+	Binary(String, String, String), // Left operand, operator and right operand
+	Word(String), // True when it expands to a non-empty string
+}
+
+/// Parse the words between `[[` and `]]`. Unquoted `&&`, `||`, `(`, `)` and
+/// `!` are operators; a quoted one is just a string.
+pub fn parse_conditional(words: &[String]) -> Result<Conditional, String> {
+	let words: Vec<&str> = words.iter().map(String::as_str).collect();
+	let mut parser = Parser { args: &words, pos: 0 };
+	let expr = parser.conditional_or()?;
+	match parser.peek(0) {
+		None => Ok(expr),
+		Some(word) => Err(format!("syntax error in conditional expression: unexpected token `{word}'")),
+	}
+}
+
+impl<'a> Parser<'a> {
+	fn conditional_or(&mut self) -> Result<Conditional, String> {
+		let mut expr = self.conditional_and()?;
+		while self.peek(0) == Some("||") {
+			self.pos += 1;
+			expr = Conditional::Or(Box::new(expr), Box::new(self.conditional_and()?));
+		}
+		Ok(expr)
+	}
+
+	fn conditional_and(&mut self) -> Result<Conditional, String> {
+		let mut expr = self.conditional_not()?;
+		while self.peek(0) == Some("&&") {
+			self.pos += 1;
+			expr = Conditional::And(Box::new(expr), Box::new(self.conditional_not()?));
+		}
+		Ok(expr)
+	}
+
+	fn conditional_not(&mut self) -> Result<Conditional, String> {
+		if self.peek(0) == Some("!") {
+			self.pos += 1;
+			return Ok(Conditional::Not(Box::new(self.conditional_not()?)));
+		}
+		self.conditional_primary()
+	}
+
+	fn conditional_primary(&mut self) -> Result<Conditional, String> {
+		let Some(first) = self.peek(0).filter(|word| !matches!(*word, "&&" | "||" | ")")) else {
+			return Err("syntax error in conditional expression".to_owned());
+		};
+		if first == "(" {
+			self.pos += 1;
+			let expr = self.conditional_or()?;
+			if self.peek(0) != Some(")") {
+				return Err("syntax error in conditional expression: expected `)'".to_owned());
+			}
+			self.pos += 1;
+			return Ok(expr);
+		}
+
+		match (self.peek(1), self.peek(2)) {
+			(Some("=~"), Some(_)) => {
+				self.pos += 2;
+				return Ok(Conditional::Binary(first.to_owned(), "=~".to_owned(), self.regex()));
+			},
+			(Some(op), Some(right)) if is_binary_operator(op) => {
+				self.pos += 3;
+				return Ok(Conditional::Binary(first.to_owned(), op.to_owned(), right.to_owned()));
+			},
+			_ => {},
+		}
+		if is_unary_operator(first) {
+			if let Some(operand) = self.peek(1) {
+				self.pos += 2;
+				return Ok(Conditional::Unary(first.to_owned(), operand.to_owned()));
+			}
+		}
+		self.pos += 1;
+		Ok(Conditional::Word(first.to_owned()))
+	}
+
+	/// The regular expression after `=~`. The tokenizer splits unquoted `(`,
+	/// `)` and `|` off as operators, so the pieces are glued back together up
+	/// to a `&&`, `||` or a `)` closing an enclosing group.
+	fn regex(&mut self) -> String {
+		let mut regex = String::new();
+		let mut depth = 0;
+		while let Some(word) = self.peek(0) {
+			match word {
+				"&&" | "||" => break,
+				"(" => depth += 1,
+				")" if depth == 0 => break,
+				")" => depth -= 1,
+				_ => {},
+			}
+			regex.push_str(word);
+			self.pos += 1;
+		}
+		regex
+	}
+}
+
+impl Conditional {
+	/// Evaluate the expression, expanding each operand without field
+	/// splitting or globbing. The right side of `==` and `!=` is a pattern
+	/// and that of `=~` an extended regular expression, whose match and
+	/// groups are stored in BASH_REMATCH.
+	pub fn evaluate(&self, shell: &mut ShellState) -> Result<bool, String> {
+		Ok(match self {
+			Conditional::Not(expr) => !expr.evaluate(shell)?,
+			Conditional::And(left, right) => left.evaluate(shell)? && right.evaluate(shell)?,
+			Conditional::Or(left, right) => left.evaluate(shell)? || right.evaluate(shell)?,
+			Conditional::Unary(op, operand) => unary(op, &expand::expand_case_word(operand, shell)?),
+			Conditional::Word(word) => !expand::expand_case_word(word, shell)?.is_empty(),
+			Conditional::Binary(left, op, right) => {
+				let left = expand::expand_case_word(left, shell)?;
+				match op.as_str() {
+					"=" | "==" => expand::glob_match(&expand::expand_case_pattern(right, shell)?, &left),
+					"!=" => !expand::glob_match(&expand::expand_case_pattern(right, shell)?, &left),
+					"=~" => {
+						let regex = expand::expand_case_word(right, shell)?;
+						let groups = sys::regex_match(&regex, &left).map_err(|e| format!("{regex}: {e}"))?;
+						set_rematch(shell, groups.as_deref().unwrap_or_default());
+						groups.is_some()
+					},
+					_ => binary(&left, op, &expand::expand_case_word(right, shell)?)?,
+				}
+			},
+		})
+	}
+}
+
+/// Store the results of `=~` in BASH_REMATCH. With no arrays in this shell,
+/// group N goes in a variable named `BASH_REMATCH[N]`, which is what
+/// `${BASH_REMATCH[N]}` reads; plain BASH_REMATCH is the whole match.
+fn set_rematch(shell: &mut ShellState, groups: &[String]) {
+	for index in groups.len().. {
+		let name = format!("BASH_REMATCH[{index}]");
+		if shell.vars.get(&name).is_none() {
+			break;
+		}
+		shell.vars.unset(&name);
+	}
+	for (index, group) in groups.iter().enumerate() {
+		shell.vars.set(&format!("BASH_REMATCH[{index}]"), group);
+	}
+	match groups.first() {
+		Some(whole) => shell.vars.set("BASH_REMATCH", whole),
+		None => shell.vars.unset("BASH_REMATCH"),
+	}
+}
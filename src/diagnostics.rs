@@ -0,0 +1,42 @@
+//! Messages from the shell itself, as opposed to the commands it runs. They
+//! all start with `myshell: `, and are coloured when stderr is a terminal.
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, IsTerminal, Write};
+
+const PREFIX: &str = "myshell: ";
+const RED: &str = "31";
+const YELLOW: &str = "33";
+
+/// Print an error to stderr.
+pub fn error(message: impl Display) {
+	print_colored(message, RED);
+}
+
+/// Print a warning, about something odd that doesn't stop the command, to stderr.
+pub fn warning(message: impl Display) {
+	print_colored(format_args!("warning: {message}"), YELLOW);
+}
+
+/// Print an error to `redirected`, the file a command's stderr was sent to,
+/// or to stderr when there is none. Files get plain text.
+pub fn error_to(redirected: Option<&File>, message: impl Display) -> io::Result<()> {
+	match redirected {
+		Some(mut file) => writeln!(file, "{PREFIX}{message}"),
+		None => {
+			error(message);
+			Ok(())
+		},
+	}
+}
+
+fn print_colored(message: impl Display, color: &str) {
+	let mut stderr = io::stderr().lock();
+	// There is nowhere left to report a failure to write an error
+	let _ = if stderr.is_terminal() {
+		writeln!(stderr, "\x1b[{color}m{PREFIX}{message}\x1b[0m")
+	} else {
+		writeln!(stderr, "{PREFIX}{message}")
+	};
+}
@@ -0,0 +1,685 @@
+//! Interactive line editor. When stdin is a terminal, input is read in raw
+//! mode one key at a time so the line can be edited in place; otherwise we
+//! fall back to plain buffered reads.
+
+use std::collections::VecDeque;
+use std::io::{self, IsTerminal, Write};
+
+use crate::unicode::{chars_width, next_boundary, prev_boundary, str_width, visible_width};
+use crate::{complete, highlight, sys, ShellState};
+
+/// Which set of key bindings the editor uses, chosen with `set -o`.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum EditingMode {
+	#[default]
+	Emacs,
+	Vi,
+}
+
+/// How long to wait after ESC for the rest of an escape sequence before
+/// taking it as the Escape key on its own.
+const ESCAPE_TIMEOUT_MS: i32 = 50;
+
+/// A decoded keypress.
+enum Key {
+	Char(char),
+	Escape,
+	Ctrl(char), // Control plus a letter, given as the lowercase letter
+	Enter,
+	Tab,
+	Backspace,
+	Delete,
+	Left,
+	Right,
+	Up,
+	Down,
+	Home,
+	End,
+	Paste(String), // Text pasted while bracketed paste mode is on
+	Unknown,
+}
+
+/// Keeps the terminal in raw mode while alive and restores it when dropped.
+/// Bracketed paste is on meanwhile, so pasted text can be told from typing.
+struct RawMode(sys::Termios);
+
+impl RawMode {
+	fn enable() -> io::Result<RawMode> {
+		let raw_mode = RawMode(sys::enable_raw_mode(0)?);
+		print!("\x1b[?2004h");
+		io::stdout().flush()?;
+		Ok(raw_mode)
+	}
+}
+
+impl Drop for RawMode {
+	fn drop(&mut self) {
+		print!("\x1b[?2004l");
+		let _ = io::stdout().flush();
+		let _ = sys::set_terminal_mode(0, &self.0);
+	}
+}
+
+/// Read pasted text up to the `ESC [201~` that ends it.
+fn read_paste() -> io::Result<String> {
+	const END: &[u8] = b"\x1b[201~";
+	let mut bytes = Vec::new();
+	while !bytes.ends_with(END) {
+		match read_byte()? {
+			Some(byte) => bytes.push(byte),
+			None => break,
+		}
+	}
+	if bytes.ends_with(END) {
+		bytes.truncate(bytes.len() - END.len());
+	}
+	Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn read_byte() -> io::Result<Option<u8>> {
+	let mut byte = [0];
+	// A signal with a trap interrupts editing, so the prompt loop can run it
+	match sys::read_fd_until_signal(0, &mut byte)? {
+		0 => Ok(None),
+		_ => Ok(Some(byte[0])),
+	}
+}
+
+/// Decode the rest of an escape sequence after ESC, e.g. `[D` for Left or
+/// `[3~` for Delete.
+fn read_escape() -> io::Result<Key> {
+	match read_byte()? {
+		Some(b'[') => {
+			let mut params = String::new();
+			let final_byte = loop {
+				match read_byte()? {
+					Some(b @ 0x40..=0x7e) => break b,
+					Some(b) => params.push(b as char),
+					None => return Ok(Key::Unknown),
+				}
+			};
+			Ok(match (params.as_str(), final_byte) {
+				("", b'A') => Key::Up,
+				("", b'B') => Key::Down,
+				("", b'C') => Key::Right,
+				("", b'D') => Key::Left,
+				("", b'H') | ("1" | "7", b'~') => Key::Home,
+				("", b'F') | ("4" | "8", b'~') => Key::End,
+				("3", b'~') => Key::Delete,
+				("200", b'~') => Key::Paste(read_paste()?),
+				_ => Key::Unknown,
+			})
+		},
+		Some(b'O') => Ok(match read_byte()? {
+			Some(b'H') => Key::Home,
+			Some(b'F') => Key::End,
+			_ => Key::Unknown,
+		}),
+		_ => Ok(Key::Unknown),
+	}
+}
+
+/// Read one keypress, or None at end of input.
+fn read_key() -> io::Result<Option<Key>> {
+	let Some(byte) = read_byte()? else {
+		return Ok(None);
+	};
+	let key = match byte {
+		b'\r' | b'\n' => Key::Enter,
+		b'\t' => Key::Tab,
+		0x7f | 0x08 => Key::Backspace,
+		0x1b if !sys::wait_readable(0, ESCAPE_TIMEOUT_MS)? => Key::Escape,
+		0x1b => read_escape()?,
+		0x01..=0x1a => Key::Ctrl((b'a' + byte - 1) as char),
+		0x00..=0x1f => Key::Unknown,
+		0x80.. => {
+			// The leading byte of a UTF-8 sequence says how many bytes follow
+			let len = byte.leading_ones() as usize;
+			let mut bytes = vec![byte];
+			for _ in 1..len.min(4) {
+				bytes.extend(read_byte()?);
+			}
+			match std::str::from_utf8(&bytes).ok().and_then(|s| s.chars().next()) {
+				Some(ch) => Key::Char(ch),
+				None => Key::Unknown,
+			}
+		},
+		_ => Key::Char(byte as char),
+	};
+	Ok(Some(key))
+}
+
+/// The line being edited and the cursor position within it, in chars. The
+/// cursor moves a grapheme cluster at a time, so it never splits an accented
+/// letter or an emoji sequence.
+#[derive(Clone, Default)]
+struct Line {
+	chars: Vec<char>,
+	cursor: usize,
+}
+
+impl Line {
+	/// A line holding `text`, with the cursor at its end.
+	fn from_text(text: &str) -> Line {
+		let chars: Vec<char> = text.chars().collect();
+		Line { cursor: chars.len(), chars }
+	}
+
+	fn insert(&mut self, ch: char) {
+		self.chars.insert(self.cursor, ch);
+		self.cursor += 1;
+	}
+
+	/// Where the cursor goes for Left.
+	fn left(&self) -> usize {
+		prev_boundary(&self.chars, self.cursor)
+	}
+
+	/// Where the cursor goes for Right.
+	fn right(&self) -> usize {
+		next_boundary(&self.chars, self.cursor)
+	}
+
+	fn delete_back(&mut self) {
+		let start = self.left();
+		self.chars.drain(start..self.cursor);
+		self.cursor = start;
+	}
+
+	fn delete_forward(&mut self) {
+		let end = self.right();
+		self.chars.drain(self.cursor..end);
+	}
+
+	/// Ctrl-W: delete the whitespace-delimited word before the cursor.
+	fn delete_word_back(&mut self) {
+		let mut start = self.cursor;
+		while start > 0 && self.chars[start - 1].is_whitespace() {
+			start -= 1;
+		}
+		while start > 0 && !self.chars[start - 1].is_whitespace() {
+			start -= 1;
+		}
+		self.chars.drain(start..self.cursor);
+		self.cursor = start;
+	}
+
+	/// Tab: complete the word before the cursor. A single candidate is
+	/// inserted whole, followed by a space unless it is a directory the user
+	/// may want to go into; several are completed as far as they agree.
+	/// Returns the candidates when there was nothing to insert.
+	fn complete(&mut self, shell: &ShellState) -> Option<Vec<String>> {
+		let (start, candidates) = complete::complete(&self.chars[..self.cursor], shell);
+		let replacement: Vec<char> = match candidates.as_slice() {
+			[] => return Some(candidates),
+			[only] => only.chars().chain((!only.ends_with('/')).then_some(' ')).collect(),
+			[first, rest @ ..] => {
+				let mut common: Vec<char> = first.chars().collect();
+				for other in rest {
+					let len = common.iter().zip(other.chars()).take_while(|(a, b)| **a == *b).count();
+					common.truncate(len);
+				}
+				if common.len() <= self.cursor - start {
+					return Some(candidates);
+				}
+				common
+			},
+		};
+		let len = replacement.len();
+		self.chars.splice(start..self.cursor, replacement);
+		self.cursor = start + len;
+		None
+	}
+
+	fn text(&self) -> String {
+		self.chars.iter().collect()
+	}
+
+	/// Where vi's `w` goes: the start of the next word.
+	fn next_word_start(&self) -> usize {
+		let mut i = self.cursor;
+		if let Some(&ch) = self.chars.get(i) {
+			let class = char_class(ch);
+			while i < self.chars.len() && class != 0 && char_class(self.chars[i]) == class {
+				i += 1;
+			}
+		}
+		while i < self.chars.len() && self.chars[i].is_whitespace() {
+			i += 1;
+		}
+		i
+	}
+
+	/// Where vi's `b` goes: the start of this word, or of the previous one.
+	fn word_start(&self) -> usize {
+		let mut i = self.cursor;
+		while i > 0 && self.chars[i - 1].is_whitespace() {
+			i -= 1;
+		}
+		if i > 0 {
+			let class = char_class(self.chars[i - 1]);
+			while i > 0 && char_class(self.chars[i - 1]) == class {
+				i -= 1;
+			}
+		}
+		i
+	}
+
+	/// Where vi's `e` goes: the last char of this word, or of the next one.
+	fn word_end(&self) -> usize {
+		let mut i = self.cursor + 1;
+		while i < self.chars.len() && self.chars[i].is_whitespace() {
+			i += 1;
+		}
+		if let Some(&ch) = self.chars.get(i) {
+			let class = char_class(ch);
+			while i + 1 < self.chars.len() && char_class(self.chars[i + 1]) == class {
+				i += 1;
+			}
+		}
+		i.min(self.chars.len().saturating_sub(1))
+	}
+
+	/// The word, or run of blanks, under the cursor, for vi's `iw`.
+	fn inner_word(&self) -> (usize, usize) {
+		let Some(&ch) = self.chars.get(self.cursor) else {
+			return (self.cursor, self.cursor);
+		};
+		let class = char_class(ch);
+		let mut start = self.cursor;
+		while start > 0 && char_class(self.chars[start - 1]) == class {
+			start -= 1;
+		}
+		let mut end = self.cursor;
+		while end < self.chars.len() && char_class(self.chars[end]) == class {
+			end += 1;
+		}
+		(start, end)
+	}
+
+	fn delete_range(&mut self, start: usize, end: usize) {
+		let end = end.min(self.chars.len());
+		if start < end {
+			self.chars.drain(start..end);
+		}
+		self.cursor = start.min(self.chars.len());
+	}
+}
+
+/// vi distinguishes blanks (0), word characters (1) and punctuation (2).
+fn char_class(ch: char) -> u8 {
+	if ch.is_whitespace() {
+		0
+	} else if ch.is_alphanumeric() || ch == '_' {
+		1
+	} else {
+		2
+	}
+}
+
+/// Where a vi motion key moves the cursor, and whether an operator applied
+/// to it includes the char it lands on (as for `e` and `$`).
+fn vi_motion(line: &Line, key: char) -> Option<(usize, bool)> {
+	Some(match key {
+		'h' => (line.left(), false),
+		'l' | ' ' => (line.right(), false),
+		'0' => (0, false),
+		'^' => (line.chars.iter().position(|c| !c.is_whitespace()).unwrap_or(0), false),
+		'$' => (line.chars.len(), true),
+		'w' => (line.next_word_start(), false),
+		'b' => (line.word_start(), false),
+		'e' => (line.word_end(), true),
+		_ => return None,
+	})
+}
+
+/// Run a vi normal-mode command other than history and Enter, reading any
+/// further keys it needs (`dw`, `ciw`, `rx`). Returns whether it switches to
+/// insert mode.
+fn vi_command(line: &mut Line, key: char) -> io::Result<bool> {
+	let len = line.chars.len();
+	match key {
+		'i' => return Ok(true),
+		'a' => {
+			line.cursor = line.right();
+			return Ok(true);
+		},
+		'I' => {
+			line.cursor = vi_motion(line, '^').unwrap().0;
+			return Ok(true);
+		},
+		'A' => {
+			line.cursor = len;
+			return Ok(true);
+		},
+		'x' => line.delete_forward(),
+		'X' => line.delete_back(),
+		'D' => line.chars.truncate(line.cursor),
+		'C' => {
+			line.chars.truncate(line.cursor);
+			return Ok(true);
+		},
+		'S' => {
+			*line = Line::default();
+			return Ok(true);
+		},
+		'r' => {
+			if let (Some(Key::Char(ch)), true) = (read_key()?, line.cursor < len) {
+				let end = line.right();
+				line.chars.splice(line.cursor..end, [ch]);
+			}
+		},
+		'd' | 'c' => {
+			let Some(Key::Char(motion)) = read_key()? else {
+				return Ok(false);
+			};
+			let (start, end) = match motion {
+				_ if motion == key => (0, len), // `dd` and `cc` take the whole line
+				'i' => match read_key()? {
+					Some(Key::Char('w')) => line.inner_word(),
+					_ => return Ok(false),
+				},
+				// Like vi, `cw` on a word changes only to the end of the word
+				'w' if key == 'c' && line.chars.get(line.cursor).is_some_and(|c| !c.is_whitespace()) => {
+					(line.cursor, line.word_end() + 1)
+				},
+				_ => match vi_motion(line, motion) {
+					Some((target, true)) if target >= line.cursor => (line.cursor, next_boundary(&line.chars, target)),
+					Some((target, false)) if target >= line.cursor => (line.cursor, target),
+					Some((target, _)) => (target, line.cursor),
+					None => return Ok(false),
+				},
+			};
+			line.delete_range(start, end);
+			return Ok(key == 'c');
+		},
+		_ => {
+			if let Some((target, _)) = vi_motion(line, key) {
+				line.cursor = target;
+			}
+		},
+	}
+	Ok(false)
+}
+
+/// Print completion candidates below the line in columns, sorted down each
+/// column like `ls`. Paths are shown by their last component.
+fn list_candidates(candidates: &[String]) -> io::Result<()> {
+	let names: Vec<&str> = candidates
+		.iter()
+		.map(|c| {
+			let base_len = c.trim_end_matches('/').rfind('/').map_or(0, |i| i + 1);
+			&c[base_len..]
+		})
+		.collect();
+	let width = names.iter().map(|n| str_width(n)).max().unwrap_or(0) + 2;
+	let term_width = sys::terminal_width(1).ok().filter(|&w| w > 0).unwrap_or(80);
+	let columns = (term_width / width).max(1);
+	let rows = names.len().div_ceil(columns);
+
+	let mut out = io::stdout().lock();
+	writeln!(out)?;
+	for row in 0..rows {
+		let line: String = names
+			.iter()
+			.skip(row)
+			.step_by(rows)
+			.map(|name| format!("{name}{}", " ".repeat(width - str_width(name))))
+			.collect();
+		writeln!(out, "{}", line.trim_end())?;
+	}
+	Ok(())
+}
+
+/// The rest of the most recent history entry that starts with the line, as
+/// a suggestion to show after it. Only offered with the cursor at the end.
+fn suggestion(entries: &[String], line: &Line) -> String {
+	if line.chars.is_empty() || line.cursor < line.chars.len() {
+		return String::new();
+	}
+	let text = line.text();
+	entries
+		.iter()
+		.rev()
+		.find_map(|entry| entry.strip_prefix(&text).filter(|rest| !rest.is_empty()))
+		.unwrap_or_default()
+		.to_owned()
+}
+
+/// Clear the terminal and move the cursor to the top left corner. Whatever
+/// was being edited should be redrawn afterwards, e.g. with `render`.
+pub fn clear_screen() -> io::Result<()> {
+	let mut out = io::stdout().lock();
+	write!(out, "\x1b[H\x1b[2J")?;
+	out.flush()
+}
+
+/// Redraw the prompt and highlighted line, with `suggestion` dimmed after
+/// it, then put the terminal cursor where it belongs.
+fn render(prompt: &str, line: &Line, suggestion: &str, shell: &ShellState) -> io::Result<()> {
+	let mut out = io::stdout().lock();
+	write!(out, "\r{prompt}{}", highlight::highlight(&line.chars, shell))?;
+	if !suggestion.is_empty() {
+		write!(out, "\x1b[2m{suggestion}\x1b[0m")?;
+	}
+	write!(out, "\x1b[K\r")?;
+	let column = visible_width(prompt) + chars_width(&line.chars[..line.cursor]);
+	if column > 0 {
+		write!(out, "\x1b[{column}C")?;
+	}
+	out.flush()
+}
+
+/// Ctrl-R: search the history backwards for entries containing the typed
+/// text, Ctrl-R again skipping to older matches. Returns the line to go on
+/// editing and the key that ended the search, if it should still take effect
+/// (e.g. Enter runs the match). Ctrl-G cancels, restoring `original`.
+fn reverse_search(entries: &[String], original: &Line) -> io::Result<(Line, Option<Key>)> {
+	let mut query = String::new();
+	let mut found: Option<usize> = None;
+	let mut failed = false;
+	loop {
+		let shown = found.map_or("", |i| entries[i].as_str());
+		let mut out = io::stdout().lock();
+		let status = if failed { "failed reverse-i-search" } else { "reverse-i-search" };
+		write!(out, "\r({status})`{query}': {shown}\x1b[K")?;
+		out.flush()?;
+		drop(out);
+
+		let accepted = || found.map_or_else(|| original.clone(), |i| Line::from_text(&entries[i]));
+		let Some(key) = read_key()? else {
+			return Ok((accepted(), None));
+		};
+		// Where to search back from: the current match is kept while it still matches
+		let before = match key {
+			Key::Char(ch) => {
+				query.push(ch);
+				found.map_or(entries.len(), |i| i + 1)
+			},
+			Key::Backspace => {
+				query.pop();
+				entries.len()
+			},
+			Key::Ctrl('r') => found.unwrap_or(entries.len()),
+			Key::Ctrl('g') => return Ok((original.clone(), None)),
+			other => return Ok((accepted(), Some(other))),
+		};
+
+		if query.is_empty() {
+			found = None;
+			failed = false;
+			continue;
+		}
+		match entries[..before].iter().rposition(|entry| entry.contains(&query)) {
+			Some(i) => {
+				found = Some(i);
+				failed = false;
+			},
+			None => failed = true,
+		}
+	}
+}
+
+#[derive(Default)]
+pub struct LineEditor {
+	queued: VecDeque<(String, bool)>, // Lines pasted ahead, and whether each was ended by a newline
+}
+
+impl LineEditor {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Show `prompt` and read a line into `buf`, newline included, like
+	/// `Stdin::read_line`. Returns the number of bytes read, 0 at end of input.
+	/// Up and Down step through the shell's history, with the typed line kept
+	/// as a draft, and Tab completes command and file names. Ctrl-C abandons
+	/// the line with an `Interrupted` error; Ctrl-D on an empty line means end
+	/// of input and otherwise deletes the char under the cursor.
+	pub fn read_line(&mut self, prompt: &str, buf: &mut String, shell: &ShellState) -> io::Result<usize> {
+		print!("{prompt}");
+		io::stdout().flush()?;
+		if !io::stdin().is_terminal() {
+			return io::stdin().read_line(buf);
+		}
+		// Only the prompt's last line is redrawn along with the text
+		let prompt = prompt.rsplit('\n').next().unwrap_or_default();
+
+		let raw_mode = RawMode::enable()?;
+		let mut line = Line::default();
+		let mut pending = None; // A key to handle before reading more
+
+		// Lines pasted earlier are entered as if typed; the last may still need editing
+		if let Some((text, complete)) = self.queued.pop_front() {
+			line = Line::from_text(&text);
+			render(prompt, &line, "", shell)?;
+			if complete {
+				pending = Some(Key::Enter);
+			}
+		}
+
+		let entries = shell.history.entries();
+		let mut position = entries.len(); // Which entry is shown; the end means the draft
+		let mut draft = String::new();
+		let mut last_was_tab = false;
+		let mut suggested = String::new(); // The suggestion currently shown
+		let vi = shell.editing_mode == EditingMode::Vi;
+		let mut vi_normal = false; // In vi mode, whether keys are commands rather than text
+		loop {
+			let Some(key) = pending.take().map_or_else(read_key, |key| Ok(Some(key)))? else {
+				if line.chars.is_empty() {
+					return Ok(0);
+				}
+				break;
+			};
+			// A second Tab in a row lists the candidates the first couldn't choose from
+			let repeated_tab = last_was_tab && matches!(key, Key::Tab);
+			last_was_tab = matches!(key, Key::Tab);
+
+			// In vi's normal mode, keys that have an emacs counterpart become
+			// that key; the other commands are handled here
+			let key = match key {
+				Key::Escape if vi && !vi_normal => {
+					vi_normal = true;
+					Key::Left
+				},
+				Key::Char(ch) if vi_normal => match ch {
+					'k' => Key::Up,
+					'j' => Key::Down,
+					_ => {
+						vi_normal = !vi_command(&mut line, ch)?;
+						Key::Unknown
+					},
+				},
+				key => key,
+			};
+
+			match key {
+				Key::Enter => break,
+				Key::Char(ch) => line.insert(ch),
+				Key::Tab => match line.complete(shell) {
+					None => {},
+					Some(candidates) if repeated_tab && candidates.len() > 1 => list_candidates(&candidates)?,
+					Some(_) => print!("\x07"), // Ring the bell when there's nothing to insert
+				},
+				Key::Backspace => line.delete_back(),
+				Key::Ctrl('d') if line.chars.is_empty() => return Ok(0),
+				Key::Delete | Key::Ctrl('d') => line.delete_forward(),
+				Key::Left | Key::Ctrl('b') => line.cursor = line.left(),
+				Key::Right | Key::Ctrl('f') if !suggested.is_empty() => {
+					// Accept the suggestion
+					line = Line::from_text(&(line.text() + &suggested));
+				},
+				Key::Right | Key::Ctrl('f') => line.cursor = line.right(),
+				Key::Up | Key::Ctrl('p') => {
+					if position == 0 {
+						continue;
+					}
+					if position == entries.len() {
+						draft = line.text();
+					}
+					position -= 1;
+					line = Line::from_text(&entries[position]);
+				},
+				Key::Down | Key::Ctrl('n') => {
+					if position == entries.len() {
+						continue;
+					}
+					position += 1;
+					line = Line::from_text(entries.get(position).unwrap_or(&draft));
+				},
+				Key::Home | Key::Ctrl('a') => line.cursor = 0,
+				Key::End | Key::Ctrl('e') => line.cursor = line.chars.len(),
+				Key::Ctrl('k') => line.chars.truncate(line.cursor),
+				Key::Ctrl('u') => {
+					line.chars.drain(..line.cursor);
+					line.cursor = 0;
+				},
+				Key::Ctrl('w') => line.delete_word_back(),
+				Key::Ctrl('r') => (line, pending) = reverse_search(entries, &line)?,
+				Key::Ctrl('l') => clear_screen()?, // The line is redrawn below
+				Key::Paste(text) => {
+					// Pasted text is inserted as is, even tabs. Further lines are
+					// queued up so each is entered in turn, rather than being run
+					// while the rest of the paste is still arriving
+					let text = text.replace("\r\n", "\n").replace('\r', "\n");
+					let mut lines = text.split('\n');
+					for ch in lines.next().unwrap_or_default().chars() {
+						line.insert(ch);
+					}
+					let rest: Vec<&str> = lines.collect();
+					if let Some((last, complete)) = rest.split_last() {
+						self.queued.extend(complete.iter().map(|l| (l.to_string(), true)));
+						if !last.is_empty() {
+							self.queued.push_back((last.to_string(), false));
+						}
+						pending = Some(Key::Enter);
+					}
+				},
+				Key::Ctrl('c') => {
+					render(prompt, &line, "", shell)?;
+					drop(raw_mode);
+					println!("^C");
+					return Err(io::ErrorKind::Interrupted.into());
+				},
+				Key::Ctrl(_) | Key::Escape | Key::Unknown => {},
+			}
+			if vi_normal {
+				// The cursor sits on a char in normal mode, never past the end
+				line.cursor = line.cursor.min(prev_boundary(&line.chars, line.chars.len()));
+			}
+			suggested = suggestion(entries, &line);
+			render(prompt, &line, &suggested, shell)?;
+		}
+
+		if !suggested.is_empty() {
+			render(prompt, &line, "", shell)?; // Don't leave the suggestion on screen
+		}
+		drop(raw_mode);
+		println!();
+		let text = line.text();
+		buf.push_str(&text);
+		buf.push('\n');
+		Ok(text.len() + 1)
+	}
+}
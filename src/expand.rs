@@ -0,0 +1,875 @@
+//! Word expansion: everything that turns a raw word from the tokenizer into
+//! the final argument strings, in bash's order: brace, tilde, parameter,
+//! command and arithmetic expansion, field splitting, and quote removal.
+
+use std::fs;
+
+use crate::options::Shopt;
+use crate::{arith, capture_output, process_substitution, ParsedCommand, RedirectMode, ShellState, TokenizerState};
+
+pub type CharStream<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+/// Consume the body of a `$(...)` whose opening `$(` was already read, returning
+/// it without the closing paren. Quotes and nested parentheses are skipped over,
+/// so `$(echo ")")` and `$(a $(b))` end at the right place.
+pub fn scan_command_substitution(chars: &mut CharStream) -> String {
+	let mut body = String::new();
+	let mut depth = 0;
+	let mut quote: Option<char> = None;
+
+	while let Some(ch) = chars.next() {
+		match (quote, ch) {
+			(Some(q), _) if ch == q => quote = None,
+			(Some('\''), _) => {},
+			(_, '\\') => {
+				body.push(ch);
+				if let Some(escaped) = chars.next() {
+					body.push(escaped);
+				}
+				continue;
+			},
+			(Some(_), _) => {},
+			(None, '\'' | '"') => quote = Some(ch),
+			(None, '(') => depth += 1,
+			(None, ')') if depth == 0 => return body,
+			(None, ')') => depth -= 1,
+			_ => {},
+		}
+		body.push(ch);
+	}
+
+	body
+}
+
+/// Consume the body of a backquoted substitution whose opening `` ` `` was
+/// already read. Backslashes are kept; they are interpreted on expansion.
+pub fn scan_backticks(chars: &mut CharStream) -> String {
+	let mut body = String::new();
+	while let Some(ch) = chars.next() {
+		match ch {
+			'`' => break,
+			'\\' => {
+				body.push(ch);
+				if let Some(escaped) = chars.next() {
+					body.push(escaped);
+				}
+			},
+			_ => body.push(ch),
+		}
+	}
+	body
+}
+
+/// Expand the parameter following a `$` that was just consumed from `chars`:
+/// `$?`, `$NAME`, `${NAME}`, `$(command)` or `$((expression))`. A `$` not
+/// followed by any of these is literal.
+fn expand_dollar(chars: &mut CharStream, shell: &ShellState) -> Result<String, String> {
+	let expanded = match chars.peek() {
+		Some(&c) if matches!(c, '?' | '#' | '@' | '*' | '-' | '!') || c.is_ascii_digit() => {
+			// Special parameters are a single character: `$10` is `$1` then `0`
+			chars.next();
+			parameter(&c.to_string(), shell)?
+		},
+		Some('(') => {
+			chars.next();
+			let arithmetic = chars.peek() == Some(&'(');
+			let body = scan_command_substitution(chars);
+			if arithmetic {
+				// The scanned body still has the inner parentheses of `$((...))`
+				let inner = body.strip_prefix('(').and_then(|b| b.strip_suffix(')')).unwrap_or(&body);
+				let expr = expand_word(inner, shell)?;
+				let value = arith::evaluate(&expr, &|name| shell.vars.get(name).map(str::to_owned))?;
+				value.to_string()
+			} else {
+				capture_output(&body, shell)
+			}
+		},
+		Some('{') => {
+			chars.next();
+			let name: String = chars.by_ref().take_while(|&c| c != '}').collect();
+			parameter(&name, shell)?
+		},
+		Some(&c) if c.is_ascii_alphabetic() || c == '_' => {
+			let mut name = String::new();
+			while let Some(&c) = chars.peek() {
+				if !(c.is_ascii_alphanumeric() || c == '_') {
+					break;
+				}
+				name.push(c);
+				chars.next();
+			}
+			parameter(&name, shell)?
+		},
+		_ => "$".to_owned(),
+	};
+	Ok(expanded)
+}
+
+/// The value of a parameter: a variable, a positional parameter like `1`
+/// (or `0`, the shell or script name), or one of the special parameters
+/// `?`, `!` (the last command put in the background), `#` (the number of
+/// positional parameters), and `@` or `*` (all of them, joined by spaces).
+/// Unset ones are empty, or an error with `set -u`.
+fn parameter(name: &str, shell: &ShellState) -> Result<String, String> {
+	let value = match name {
+		"?" => return Ok(shell.last_status.to_string()),
+		"!" => shell.last_background.map(|pid| pid.to_string()),
+		"-" => return Ok(shell.options.flags()),
+		"#" => return Ok(shell.positional.len().to_string()),
+		"@" | "*" => return Ok(shell.positional.join(" ")),
+		"0" => return Ok(shell.arg0.clone()),
+		_ if name.bytes().all(|b| b.is_ascii_digit()) => {
+			let index: usize = name.parse().unwrap_or(0);
+			shell.positional.get(index.wrapping_sub(1)).cloned()
+		},
+		_ => shell.vars.get(name).map(str::to_owned),
+	};
+	match value {
+		Some(value) => Ok(value),
+		None if shell.options.nounset => Err(format!("{name}: unbound variable")),
+		None => Ok(String::new()),
+	}
+}
+
+/// Expand a backquoted command substitution whose opening `` ` `` was just
+/// consumed. Inside backquotes, a backslash only escapes `$`, `` ` `` and `\`.
+fn expand_backticks(chars: &mut CharStream, shell: &ShellState) -> String {
+	let mut source = String::new();
+	let raw = scan_backticks(chars);
+	let mut body = raw.chars().peekable();
+	while let Some(ch) = body.next() {
+		if ch == '\\' && matches!(body.peek(), Some('$' | '`' | '\\')) {
+			source.push(body.next().unwrap());
+		} else {
+			source.push(ch);
+		}
+	}
+	capture_output(&source, shell)
+}
+
+/// Expand a here-document body. Quotes are ordinary characters here, and a
+/// backslash only escapes `$`, `` ` `` and itself.
+fn expand_heredoc(body: &str, shell: &ShellState) -> Result<String, String> {
+	let mut expanded = String::new();
+	let mut chars = body.chars().peekable();
+
+	while let Some(ch) = chars.next() {
+		match ch {
+			'\\' if matches!(chars.peek(), Some('$' | '`' | '\\')) => {
+				expanded.push(chars.next().unwrap());
+			},
+			'$' => expanded.push_str(&expand_dollar(&mut chars, shell)?),
+			'`' => expanded.push_str(&expand_backticks(&mut chars, shell)),
+			_ => expanded.push(ch),
+		}
+	}
+
+	Ok(expanded)
+}
+
+/// Read up to `max` digits in the given radix and return the code they spell.
+fn take_digits(chars: &mut CharStream, radix: u32, max: usize) -> Option<u32> {
+	let mut value = None;
+	for _ in 0..max {
+		let Some(digit) = chars.peek().and_then(|c| c.to_digit(radix)) else {
+			break;
+		};
+		chars.next();
+		value = Some(value.unwrap_or(0) * radix + digit);
+	}
+	value
+}
+
+/// Decode the contents of `$'...'` whose opening `$'` was just consumed, up
+/// to and including the closing quote. C escapes are interpreted: `\n`, `\t`,
+/// `\xHH`, `\NNN` octal, `\uHHHH`, `\cX` control characters and so on.
+fn expand_ansi_c_quote(chars: &mut CharStream) -> String {
+	let mut decoded = String::new();
+	while let Some(ch) = chars.next() {
+		match ch {
+			'\'' => break,
+			'\\' => {
+				let Some(escaped) = chars.next() else {
+					decoded.push('\\');
+					break;
+				};
+				let code = match escaped {
+					'n' => Some(0x0a),
+					't' => Some(0x09),
+					'r' => Some(0x0d),
+					'a' => Some(0x07),
+					'b' => Some(0x08),
+					'e' | 'E' => Some(0x1b),
+					'f' => Some(0x0c),
+					'v' => Some(0x0b),
+					'\\' | '\'' | '"' | '?' => Some(escaped as u32),
+					'x' => take_digits(chars, 16, 2),
+					'u' => take_digits(chars, 16, 4),
+					'U' => take_digits(chars, 16, 8),
+					'c' => chars.next().map(|c| c.to_ascii_uppercase() as u32 ^ 0x40),
+					'0'..='7' => {
+						let mut value = escaped.to_digit(8).unwrap();
+						for _ in 0..2 {
+							let Some(digit) = chars.peek().and_then(|c| c.to_digit(8)) else {
+								break;
+							};
+							chars.next();
+							value = value * 8 + digit;
+						}
+						Some(value)
+					},
+					_ => None,
+				};
+				match code.and_then(char::from_u32) {
+					Some(c) => decoded.push(c),
+					None => {
+						// Unknown escapes and invalid codes stay as written
+						decoded.push('\\');
+						decoded.push(escaped);
+					},
+				}
+			},
+			_ => decoded.push(ch),
+		}
+	}
+	decoded
+}
+
+/// Interpret the backslash escapes of `echo -e` and `printf`: those of `$'...'`,
+/// except that `\c` ends the output. Octal codes are written `\0NNN` when
+/// `zero_octal` is set, as for `echo` and `%b`, and `\NNN` in a `printf` format.
+/// Returns the decoded text and whether `\c` cut it short.
+pub fn backslash_escapes(text: &str, zero_octal: bool) -> (String, bool) {
+	let mut decoded = String::new();
+	let mut chars = text.chars().peekable();
+	while let Some(ch) = chars.next() {
+		if ch != '\\' {
+			decoded.push(ch);
+			continue;
+		}
+		let Some(escaped) = chars.next() else {
+			decoded.push('\\');
+			break;
+		};
+		let code = match escaped {
+			'n' => Some(0x0a),
+			't' => Some(0x09),
+			'r' => Some(0x0d),
+			'a' => Some(0x07),
+			'b' => Some(0x08),
+			'e' | 'E' => Some(0x1b),
+			'f' => Some(0x0c),
+			'v' => Some(0x0b),
+			'\\' => Some(0x5c),
+			'x' => take_digits(&mut chars, 16, 2),
+			'u' => take_digits(&mut chars, 16, 4),
+			'U' => take_digits(&mut chars, 16, 8),
+			'0' if zero_octal => Some(take_digits(&mut chars, 8, 3).unwrap_or(0)),
+			'0'..='7' if !zero_octal => {
+				let mut value = escaped.to_digit(8).unwrap();
+				for _ in 0..2 {
+					let Some(digit) = chars.peek().and_then(|c| c.to_digit(8)) else {
+						break;
+					};
+					chars.next();
+					value = value * 8 + digit;
+				}
+				Some(value)
+			},
+			'c' => return (decoded, true),
+			_ => None,
+		};
+		match code.and_then(char::from_u32) {
+			Some(c) => decoded.push(c),
+			None => {
+				decoded.push('\\');
+				decoded.push(escaped);
+			},
+		}
+	}
+	(decoded, false)
+}
+
+/// Collects the fields a word expands to. Results of unquoted expansions are
+/// split on IFS characters, everything else is glued onto the current field.
+/// Alongside the text, each field is also kept as a glob pattern in which
+/// quoted metacharacters are escaped, so only unquoted ones can match files.
+struct Fields {
+	fields: Vec<String>,
+	current: String,
+	pattern: String, // The current field as a glob pattern
+	globbing: bool, // Whether the current field has an unquoted `*`, `?` or `[`
+	started: bool, // Whether the current field exists, even if empty (e.g. `""`)
+	ifs: Option<String>, // None when field splitting and globbing are off
+	shopt: Shopt, // For how globs match
+}
+
+impl Fields {
+	fn push(&mut self, ch: char, quoted: bool) {
+		self.current.push(ch);
+		if quoted && matches!(ch, '*' | '?' | '[' | ']' | '\\') {
+			self.pattern.push('\\');
+		} else if !quoted && matches!(ch, '*' | '?' | '[') {
+			self.globbing = true;
+		}
+		self.pattern.push(ch);
+		self.started = true;
+	}
+
+	fn push_str(&mut self, text: &str) {
+		for ch in text.chars() {
+			self.push(ch, true);
+		}
+		self.started = true;
+	}
+
+	/// Add the result of an unquoted expansion, splitting it into fields.
+	/// Its metacharacters stay active in the pattern either way.
+	fn push_unquoted(&mut self, text: &str) {
+		let Some(ifs) = self.ifs.take() else {
+			for ch in text.chars() {
+				self.push(ch, false);
+			}
+			self.started = true;
+			return;
+		};
+		for ch in text.chars() {
+			if ifs.contains(ch) {
+				self.end_field();
+			} else {
+				self.push(ch, false);
+			}
+		}
+		self.ifs = Some(ifs);
+	}
+
+	fn end_field(&mut self) {
+		if !self.started {
+			return;
+		}
+
+		let text = std::mem::take(&mut self.current);
+		let pattern = std::mem::take(&mut self.pattern);
+		let matches = if self.globbing && self.ifs.is_some() { glob(&pattern, &self.shopt) } else { Vec::new() };
+		if matches.is_empty() {
+			// Like bash, a pattern matching nothing is passed on unchanged
+			self.fields.push(text);
+		} else {
+			self.fields.extend(matches);
+		}
+		self.globbing = false;
+		self.started = false;
+	}
+}
+
+/// Expand a raw word: remove quotes and backslashes, substitute parameters and
+/// commands, and, when `split`, split unquoted substitution results into fields
+/// and expand unquoted glob patterns against the filesystem.
+/// Single quotes keep everything literal; double quotes still allow expansion.
+fn expand_fields(word: &str, shell: &ShellState, split: bool) -> Result<Vec<String>, String> {
+	let mut fields = expand_unfinished(word, shell, split)?;
+	fields.end_field();
+	Ok(fields.fields)
+}
+
+/// Expand `word` as far as `expand_fields` does, leaving the last field open.
+fn expand_unfinished(word: &str, shell: &ShellState, split: bool) -> Result<Fields, String> {
+	let ifs = split.then(|| shell.vars.get("IFS").unwrap_or(" \t\n").to_owned());
+	let mut fields = Fields {
+		fields: Vec::new(),
+		current: String::new(),
+		pattern: String::new(),
+		globbing: false,
+		started: false,
+		ifs,
+		shopt: shell.shopt,
+	};
+	let mut state = TokenizerState::Out;
+	let mut chars = word.chars().peekable();
+
+	while let Some(ch) = chars.next() {
+		match (&state, ch) {
+			(TokenizerState::Out, '\"') => {
+				state = TokenizerState::InDoubleQuote;
+				fields.started = true;
+			},
+			(TokenizerState::Out, '\'') => {
+				state = TokenizerState::InSingleQuote;
+				fields.started = true;
+			},
+			(TokenizerState::InSingleQuote, '\'') => state = TokenizerState::Out,
+			(TokenizerState::InDoubleQuote, '\"') => state = TokenizerState::Out,
+			(TokenizerState::Out, '\\') => state = TokenizerState::BackSlashOutsideQuote,
+			(TokenizerState::InDoubleQuote, '\\') => state = TokenizerState::BackSlashInDoubleQuote,
+
+			(TokenizerState::Out, '$') if chars.peek() == Some(&'\'') => {
+				chars.next();
+				fields.push_str(&expand_ansi_c_quote(&mut chars));
+			},
+			(TokenizerState::Out, '$') => fields.push_unquoted(&expand_dollar(&mut chars, shell)?),
+			(TokenizerState::Out, '`') => fields.push_unquoted(&expand_backticks(&mut chars, shell)),
+			(TokenizerState::Out, '<' | '>') if chars.peek() == Some(&'(') => {
+				chars.next();
+				let body = scan_command_substitution(&mut chars);
+				let path = process_substitution(&body, shell, ch == '<')
+					.map_err(|e| format!("process substitution: {e}"))?;
+				fields.push_str(&path);
+			},
+			(TokenizerState::InDoubleQuote, '$') if chars.peek() == Some(&'@') && fields.ifs.is_some() => {
+				// "$@" makes each positional parameter a field of its own
+				chars.next();
+				for (i, arg) in shell.positional.iter().enumerate() {
+					if i > 0 {
+						fields.end_field();
+					}
+					fields.push_str(arg);
+				}
+				if shell.positional.is_empty() && fields.current.is_empty() {
+					fields.started = false; // With no parameters, "$@" is no field at all
+				}
+			},
+			(TokenizerState::InDoubleQuote, '$') if chars.peek() == Some(&'*') && fields.ifs.is_some() => {
+				// "$*" is a single field, joined by the first character of IFS
+				chars.next();
+				let separator = fields.ifs.as_deref().and_then(|ifs| ifs.chars().next()).map(String::from).unwrap_or_default();
+				fields.push_str(&shell.positional.join(&separator));
+			},
+			(TokenizerState::InDoubleQuote, '$') => fields.push_str(&expand_dollar(&mut chars, shell)?),
+			(TokenizerState::InDoubleQuote, '`') => fields.push_str(&expand_backticks(&mut chars, shell)),
+
+			(TokenizerState::BackSlashOutsideQuote, any) => {
+				fields.push(any, true);
+				state = TokenizerState::Out;
+			},
+
+			(TokenizerState::BackSlashInDoubleQuote, any) => {
+				if !matches!(any, '$' | '`' | '\\' | '"' | '\n') {
+					// Only $, `, \, " and newline can be escaped inside double quotes
+					fields.push('\\', true);
+				}
+				fields.push(any, true);
+				state = TokenizerState::InDoubleQuote;
+			},
+
+			(TokenizerState::Out, any) => fields.push(any, false),
+			(_, any) => fields.push(any, true),
+		}
+	}
+	Ok(fields)
+}
+
+/// Match the bracket expression at the start of `pattern` (e.g. `[a-z]` or
+/// `[!0-9]`) against `ch`. Returns how many pattern chars it spans and whether
+/// it matched, or None when there is no closing `]` and `[` is literal.
+fn match_bracket(pattern: &[char], ch: char) -> Option<(usize, bool)> {
+	let mut i = 1;
+	let negate = matches!(pattern.get(i), Some('!' | '^'));
+	if negate {
+		i += 1;
+	}
+
+	let mut matched = false;
+	let mut first = true;
+	while i < pattern.len() {
+		let mut start = pattern[i];
+		if start == ']' && !first {
+			return Some((i + 1, matched != negate));
+		}
+		first = false;
+		if start == '\\' && i + 1 < pattern.len() {
+			i += 1;
+			start = pattern[i];
+		}
+
+		if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|&end| end != ']') {
+			let end = pattern[i + 2];
+			matched |= start <= ch && ch <= end;
+			i += 3;
+		} else {
+			matched |= start == ch;
+			i += 1;
+		}
+	}
+	None
+}
+
+fn match_from(pattern: &[char], text: &[char]) -> bool {
+	match pattern.first() {
+		None => text.is_empty(),
+		Some('*') => {
+			let rest = &pattern[1..];
+			(0..=text.len()).any(|skip| match_from(rest, &text[skip..]))
+		},
+		Some('?') => !text.is_empty() && match_from(&pattern[1..], &text[1..]),
+		Some('[') => match text.first() {
+			None => false,
+			Some(&ch) => match match_bracket(pattern, ch) {
+				Some((len, matched)) => matched && match_from(&pattern[len..], &text[1..]),
+				None => ch == '[' && match_from(&pattern[1..], &text[1..]),
+			},
+		},
+		Some('\\') if pattern.len() > 1 => text.first() == Some(&pattern[1]) && match_from(&pattern[2..], &text[1..]),
+		Some(&c) => text.first() == Some(&c) && match_from(&pattern[1..], &text[1..]),
+	}
+}
+
+/// Whether `text` matches the shell pattern, with `*`, `?`, `[...]` and
+/// backslash escapes.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+	let pattern: Vec<char> = pattern.chars().collect();
+	let text: Vec<char> = text.chars().collect();
+	match_from(&pattern, &text)
+}
+
+/// Whether a pattern component has an unescaped glob metacharacter.
+fn has_glob_chars(pattern: &str) -> bool {
+	let mut chars = pattern.chars();
+	while let Some(ch) = chars.next() {
+		match ch {
+			'\\' => { chars.next(); },
+			'*' | '?' | '[' => return true,
+			_ => {},
+		}
+	}
+	false
+}
+
+/// Remove the backslashes a pattern uses to escape quoted metacharacters.
+fn unescape_pattern(pattern: &str) -> String {
+	let mut text = String::new();
+	let mut chars = pattern.chars();
+	while let Some(ch) = chars.next() {
+		match ch {
+			'\\' => text.extend(chars.next()),
+			_ => text.push(ch),
+		}
+	}
+	text
+}
+
+/// Pathname expansion: walk the pattern one `/`-separated component at a
+/// time, matching wildcard components against directory entries. Names
+/// starting with `.` only match when the pattern component does too, unless
+/// `dotglob` is on; `nocaseglob` makes matching ignore case.
+fn glob(pattern: &str, shopt: &Shopt) -> Vec<String> {
+	let mut candidates = vec![if pattern.starts_with('/') { "/".to_owned() } else { String::new() }];
+	let dirs_only = pattern.ends_with('/');
+	let join = |base: &str, name: &str| {
+		if base.is_empty() || base.ends_with('/') { format!("{base}{name}") } else { format!("{base}/{name}") }
+	};
+
+	for component in pattern.split('/').filter(|c| !c.is_empty()) {
+		let mut next = Vec::new();
+		for base in &candidates {
+			if !has_glob_chars(component) {
+				let path = join(base, &unescape_pattern(component));
+				if fs::symlink_metadata(&path).is_ok() {
+					next.push(path);
+				}
+				continue;
+			}
+
+			let Ok(entries) = fs::read_dir(if base.is_empty() { "." } else { base }) else {
+				continue;
+			};
+			let mut names: Vec<String> = entries
+				.filter_map(Result::ok)
+				.filter_map(|e| e.file_name().into_string().ok())
+				.filter(|name| !name.starts_with('.') || shopt.dotglob || component.starts_with('.') || component.starts_with("\\."))
+				.filter(|name| if shopt.nocaseglob {
+					glob_match(&component.to_lowercase(), &name.to_lowercase())
+				} else {
+					glob_match(component, name)
+				})
+				.collect();
+			names.sort();
+			next.extend(names.iter().map(|name| join(base, name)));
+		}
+		candidates = next;
+	}
+
+	if dirs_only {
+		candidates.retain(|path| fs::metadata(path).is_ok_and(|m| m.is_dir()));
+		for path in candidates.iter_mut() {
+			path.push('/');
+		}
+	}
+	candidates.sort();
+	candidates
+}
+
+/// Expand a word that must stay a single string, such as an assignment value
+/// or a redirection target, so no field splitting happens.
+fn expand_word(word: &str, shell: &ShellState) -> Result<String, String> {
+	Ok(expand_fields(word, shell, false)?.concat())
+}
+
+/// Expand the word a `case` matches against: no field splitting or globbing.
+pub fn expand_case_word(word: &str, state: &ShellState) -> Result<String, String> {
+	expand_word(&tilde_expand(word, state), state)
+}
+
+/// Expand a `case` pattern into one for `glob_match`, in which quoted
+/// metacharacters are escaped so they only match themselves.
+pub fn expand_case_pattern(word: &str, state: &ShellState) -> Result<String, String> {
+	Ok(expand_unfinished(&tilde_expand(word, state), state, false)?.pattern)
+}
+
+/// Index just past the closing bracket matching the opener at `start`,
+/// skipping quoted text. Used to step over `${...}` and `$(...)` while
+/// looking for brace expansions.
+fn skip_balanced(chars: &[char], start: usize, open: char, close: char) -> usize {
+	let mut depth = 0;
+	let mut quote: Option<char> = None;
+	let mut i = start;
+	while i < chars.len() {
+		let ch = chars[i];
+		match quote {
+			Some(q) if ch == q => quote = None,
+			Some(_) => {},
+			None if ch == '\\' => i += 1,
+			None if ch == '\'' || ch == '"' => quote = Some(ch),
+			None if ch == open => depth += 1,
+			None if ch == close => {
+				depth -= 1;
+				if depth == 0 {
+					return i + 1;
+				}
+			},
+			None => {},
+		}
+		i += 1;
+	}
+	chars.len()
+}
+
+/// Expand the contents of a `{a..b}` or `{a..b..step}` range, numeric or
+/// single characters. Numbers keep zero padding, as in `{01..10}`.
+fn brace_range(body: &str) -> Option<Vec<String>> {
+	let parts: Vec<&str> = body.split("..").collect();
+	let (start, end, step) = match parts[..] {
+		[start, end] => (start, end, 1),
+		[start, end, step] => (start, end, step.parse::<i64>().ok()?.unsigned_abs().max(1) as i64),
+		_ => return None,
+	};
+
+	if let (Ok(a), Ok(b)) = (start.parse::<i64>(), end.parse::<i64>()) {
+		let padded = |s: &str| s.trim_start_matches('-').len() > 1 && s.trim_start_matches('-').starts_with('0');
+		let width = if padded(start) || padded(end) { start.len().max(end.len()) } else { 0 };
+		let values: Vec<i64> = if a <= b {
+			(a..=b).step_by(step as usize).collect()
+		} else {
+			(b..=a).rev().step_by(step as usize).collect()
+		};
+		return Some(values.into_iter().map(|n| format!("{n:0width$}")).collect());
+	}
+
+	let (mut a_chars, mut b_chars) = (start.chars(), end.chars());
+	match (a_chars.next(), a_chars.next(), b_chars.next(), b_chars.next()) {
+		(Some(a), None, Some(b), None) if a.is_ascii_alphabetic() && b.is_ascii_alphabetic() => {
+			let (a, b) = (a as u8, b as u8);
+			let values: Vec<u8> = if a <= b {
+				(a..=b).step_by(step as usize).collect()
+			} else {
+				(b..=a).rev().step_by(step as usize).collect()
+			};
+			Some(values.into_iter().map(|c| (c as char).to_string()).collect())
+		},
+		_ => None,
+	}
+}
+
+/// Brace expansion on a raw word: `file.{txt,bak}` becomes two words and
+/// `dir{1..3}` three. Quoted braces and `${...}` are left alone, and a brace
+/// group with neither a comma nor a valid range is kept literally.
+fn brace_expand(word: &str) -> Vec<String> {
+	let chars: Vec<char> = word.chars().collect();
+	let mut quote: Option<char> = None;
+	let mut i = 0;
+
+	while i < chars.len() {
+		let ch = chars[i];
+		match quote {
+			// `$'...'` is tracked as `$`, since `\'` doesn't end it
+			Some('$') if ch == '\\' => i += 1,
+			Some('$') if ch == '\'' => quote = None,
+			Some(q) if ch == q => quote = None,
+			Some(_) => {},
+			None if ch == '\\' => i += 1,
+			None if ch == '\'' || ch == '"' => quote = Some(ch),
+			None if ch == '$' && matches!(chars.get(i + 1), Some('\'')) => {
+				quote = Some('$');
+				i += 1;
+			},
+			None if ch == '$' && matches!(chars.get(i + 1), Some('{')) => {
+				i = skip_balanced(&chars, i + 1, '{', '}');
+				continue;
+			},
+			None if ch == '$' && matches!(chars.get(i + 1), Some('(')) => {
+				i = skip_balanced(&chars, i + 1, '(', ')');
+				continue;
+			},
+			None if ch == '{' => {
+				if let Some(words) = expand_brace_group(&chars, i) {
+					return words;
+				}
+			},
+			None => {},
+		}
+		i += 1;
+	}
+
+	vec![word.to_owned()]
+}
+
+/// Try to expand the brace group opening at `open`, returning every
+/// resulting word (further groups included) or None if it isn't one.
+fn expand_brace_group(chars: &[char], open: usize) -> Option<Vec<String>> {
+	let mut depth = 0;
+	let mut quote: Option<char> = None;
+	let mut commas = Vec::new();
+	let mut close = None;
+
+	let mut i = open;
+	while i < chars.len() {
+		let ch = chars[i];
+		match quote {
+			Some('$') if ch == '\\' => i += 1,
+			Some('$') if ch == '\'' => quote = None,
+			Some(q) if ch == q => quote = None,
+			Some(_) => {},
+			None if ch == '\\' => i += 1,
+			None if ch == '\'' || ch == '"' => quote = Some(ch),
+			None if ch == '$' && matches!(chars.get(i + 1), Some('\'')) => {
+				quote = Some('$');
+				i += 1;
+			},
+			None if ch == '{' => depth += 1,
+			None if ch == '}' => {
+				depth -= 1;
+				if depth == 0 {
+					close = Some(i);
+					break;
+				}
+			},
+			None if ch == ',' && depth == 1 => commas.push(i),
+			None => {},
+		}
+		i += 1;
+	}
+
+	let close = close?;
+	let prefix: String = chars[..open].iter().collect();
+	let suffix: String = chars[close + 1..].iter().collect();
+
+	let alternatives: Vec<String> = if commas.is_empty() {
+		let body: String = chars[open + 1..close].iter().collect();
+		brace_range(&body)?
+	} else {
+		let mut bounds = vec![open];
+		bounds.extend(&commas);
+		bounds.push(close);
+		bounds.windows(2).map(|w| chars[w[0] + 1..w[1]].iter().collect()).collect()
+	};
+
+	// Nested groups inside an alternative, and later groups in the suffix,
+	// are handled by expanding each combined word again
+	Some(alternatives
+		.iter()
+		.flat_map(|alt| brace_expand(&format!("{prefix}{alt}{suffix}")))
+		.collect())
+}
+
+/// Home directory of `user` from the password database.
+fn home_of_user(user: &str) -> Option<String> {
+	let passwd = fs::read_to_string("/etc/passwd").ok()?;
+	passwd.lines().find_map(|line| {
+		let fields: Vec<&str> = line.split(':').collect();
+		(fields.len() > 5 && fields[0] == user).then(|| fields[5].to_owned())
+	})
+}
+
+/// Tilde expansion on a raw word: a leading `~`, `~/...`, `~user/...`, `~+`
+/// (PWD) or `~-` (OLDPWD) is replaced by that directory, escaped so the later
+/// quote removal leaves it alone. A quoted or unknown prefix is left as is.
+fn tilde_expand(word: &str, state: &ShellState) -> String {
+	let Some(rest) = word.strip_prefix('~') else {
+		return word.to_owned();
+	};
+	let (prefix, suffix) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+	if prefix.contains(['\'', '"', '\\', '$', '`']) {
+		return word.to_owned();
+	}
+
+	let dir = match prefix {
+		"" => state.vars.get("HOME").map(str::to_owned),
+		"+" => state.vars.get("PWD").map(str::to_owned),
+		"-" => state.vars.get("OLDPWD").map(str::to_owned),
+		user => home_of_user(user),
+	};
+	let Some(dir) = dir else {
+		return word.to_owned();
+	};
+
+	let mut escaped = String::new();
+	for ch in dir.chars() {
+		if !ch.is_alphanumeric() && !matches!(ch, '/' | '.' | '-' | '_') {
+			escaped.push('\\');
+		}
+		escaped.push(ch);
+	}
+	escaped + suffix
+}
+
+/// Expand raw words into the fields they stand for, as for a command's
+/// arguments or the list of a `for` loop: braces, tildes, parameters and
+/// substitutions, then field splitting and globbing.
+pub fn expand_words(words: &[String], state: &ShellState) -> Result<Vec<String>, String> {
+	let mut fields = Vec::new();
+	for word in words.iter().flat_map(|word| brace_expand(word)) {
+		fields.extend(expand_fields(&tilde_expand(&word, state), state, true)?);
+	}
+	Ok(fields)
+}
+
+/// Expand the words of a command in place: arguments, redirection targets
+/// and assignment values, in the order bash does.
+pub fn expand_command(command: &mut ParsedCommand, state: &ShellState) -> Result<(), String> {
+	command.argv = expand_words(&command.argv, state)?;
+
+	for redir in command.redirects.iter_mut() {
+		match &mut redir.mode {
+			RedirectMode::HereString(text) => *text = expand_word(text, state)?,
+			RedirectMode::HereDoc { expand: true, body, .. } => *body = expand_heredoc(body, state)?,
+			_ if !redir.path.as_os_str().is_empty() => {
+				let target = tilde_expand(&redir.path.to_string_lossy(), state);
+				redir.path = expand_word(&target, state)?.into();
+			},
+			_ => {},
+		}
+	}
+
+	for (_, value) in command.assignments.iter_mut() {
+		// In assignments a tilde also expands after each `:`, as in PATH=~/bin:~/.local/bin
+		let value_with_homes: Vec<String> = value.split(':').map(|part| tilde_expand(part, state)).collect();
+		*value = expand_word(&value_with_homes.join(":"), state)?;
+	}
+
+	Ok(())
+}
+
+/// Single-quote `text` so the shell reads it back as one literal word.
+pub fn quote(text: &str) -> String {
+	format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// Quote `text` only if the shell would read it as anything other than one
+/// literal word, as when showing a command with `set -x`.
+pub fn quote_if_needed(text: &str) -> String {
+	let plain = |c: char| c.is_alphanumeric() || "-_./,:+=@%^".contains(c);
+	if !text.is_empty() && text.chars().all(plain) {
+		text.to_owned()
+	} else {
+		quote(text)
+	}
+}
@@ -0,0 +1,141 @@
+//! Syntax highlighting for the line being edited: command names in green
+//! when they can be run and red when not, quoted text in yellow, and
+//! operators and redirections in cyan.
+
+use std::path::Path;
+
+use crate::{path_index, ShellState, BUILTIN_COMMANDS};
+
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+/// Whether `word` is a redirection operator such as `>`, `2>>` or `2>&1`.
+fn is_redirect(word: &str) -> bool {
+	let op = word.trim_start_matches(|c: char| c.is_ascii_digit());
+	matches!(op, "<" | ">" | ">>" | "<<" | "<<-" | "<<<" | ">|" | "&>" | "&>>")
+		|| op.strip_prefix(">&").or_else(|| op.strip_prefix("<&")).is_some_and(|fd| fd == "-" || fd.parse::<u8>().is_ok())
+}
+
+/// Whether `word` is a `NAME=value` assignment.
+fn is_assignment(word: &str) -> bool {
+	word.split_once('=').is_some_and(|(name, _)| crate::vars::is_valid_name(name))
+}
+
+fn is_runnable(name: &str, shell: &ShellState) -> bool {
+	BUILTIN_COMMANDS.contains(&name)
+		|| shell.functions.contains_key(name)
+		|| shell.path_index.find(name).is_some()
+		|| (path_index::is_path(name) && Path::new(name).is_file())
+}
+
+/// End of the word starting at `start`: the next unquoted blank or control
+/// operator. Quotes still open at the end of the line run to the end.
+fn word_end(chars: &[char], start: usize) -> usize {
+	let mut quote = None;
+	let mut i = start;
+	while i < chars.len() {
+		let ch = chars[i];
+		match quote {
+			Some(q) if ch == q => quote = None,
+			Some('"') if ch == '\\' => i += 1,
+			Some(_) => {},
+			None if ch == '\'' || ch == '"' => quote = Some(ch),
+			None if ch == '\\' => i += 1,
+			None if ch.is_whitespace() || ch == ';' || ch == '|' => break,
+			// `&` ends a word, except in redirections like `&>` and `2>&1`
+			None if ch == '&' && chars.get(i + 1) != Some(&'>') && !matches!(chars.get(i.wrapping_sub(1)), Some('>' | '<')) => break,
+			None => {},
+		}
+		i += 1;
+	}
+	i.min(chars.len())
+}
+
+/// Copy a word, showing its quoted parts in yellow.
+fn push_quoted_parts(out: &mut String, word: &[char]) {
+	let mut quote = None;
+	let mut chars = word.iter().peekable();
+	while let Some(&ch) = chars.next() {
+		match quote {
+			None if ch == '\'' || ch == '"' => {
+				quote = Some(ch);
+				out.push_str(YELLOW);
+				out.push(ch);
+				continue;
+			},
+			None if ch == '\\' => {
+				out.push(ch);
+				out.extend(chars.next());
+				continue;
+			},
+			Some(q) if ch == q => {
+				quote = None;
+				out.push(ch);
+				out.push_str(RESET);
+				continue;
+			},
+			Some('"') if ch == '\\' => {
+				out.push(ch);
+				out.extend(chars.next());
+				continue;
+			},
+			_ => {},
+		}
+		out.push(ch);
+	}
+	if quote.is_some() {
+		out.push_str(RESET);
+	}
+}
+
+/// The line with color escapes added. Only colors are inserted, so the text
+/// takes up as many columns as before.
+pub fn highlight(chars: &[char], shell: &ShellState) -> String {
+	let mut out = String::new();
+	let mut command_position = true; // Whether the next word names a command
+	let mut i = 0;
+
+	while i < chars.len() {
+		let ch = chars[i];
+		if ch.is_whitespace() {
+			out.push(ch);
+			i += 1;
+			continue;
+		}
+
+		if matches!(ch, ';' | '|' | '&') {
+			// Control operators, one or two characters long, start a new command
+			let len = if chars.get(i + 1) == Some(&ch) && ch != ';' { 2 } else { 1 };
+			out.push_str(CYAN);
+			out.extend(&chars[i..i + len]);
+			out.push_str(RESET);
+			command_position = true;
+			i += len;
+			continue;
+		}
+
+		let end = word_end(chars, i);
+		let word = &chars[i..end];
+		let text: String = word.iter().collect();
+		if is_redirect(&text) {
+			out.push_str(CYAN);
+			out.push_str(&text);
+			out.push_str(RESET);
+		} else if command_position && is_assignment(&text) {
+			push_quoted_parts(&mut out, word);
+		} else if command_position {
+			let name: String = word.iter().filter(|c| !matches!(c, '\'' | '"' | '\\')).collect();
+			out.push_str(if is_runnable(&name, shell) { GREEN } else { RED });
+			out.push_str(&text);
+			out.push_str(RESET);
+			command_position = false;
+		} else {
+			push_quoted_parts(&mut out, word);
+		}
+		i = end;
+	}
+	out
+}
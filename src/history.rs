@@ -0,0 +1,127 @@
+//! Command history, shared by the line editor and the `history` builtin.
+//! Entries are kept in a file, one per line, so they survive between sessions.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// How many entries to keep when HISTSIZE isn't set, as in bash.
+pub const DEFAULT_SIZE: usize = 500;
+
+#[derive(Clone, Default)]
+pub struct History {
+	entries: Vec<String>,
+	file: Option<PathBuf>, // Where entries are saved, if anywhere
+	limit: usize, // Most entries to keep
+}
+
+impl History {
+	/// Read the history saved in `file`, keeping the `limit` most recent
+	/// entries. The file is trimmed to match, and new entries get appended to it.
+	pub fn load(file: PathBuf, limit: usize) -> History {
+		let mut entries: Vec<String> = fs::read_to_string(&file)
+			.unwrap_or_default()
+			.lines()
+			.map(str::to_owned)
+			.collect();
+		if entries.len() > limit {
+			entries.drain(..entries.len() - limit);
+			let _ = fs::write(&file, entries.iter().map(|e| format!("{e}\n")).collect::<String>());
+		}
+		History { entries, file: Some(file), limit }
+	}
+
+	/// Record an entered command. Blank lines are not remembered. With
+	/// `append`, the line is added to the history file; otherwise the file is
+	/// rewritten with this shell's entries, dropping any other shell's.
+	pub fn add(&mut self, line: &str, append: bool) {
+		if line.trim().is_empty() || self.limit == 0 {
+			return;
+		}
+		self.entries.push(line.to_owned());
+		if self.entries.len() > self.limit {
+			self.entries.remove(0);
+		}
+
+		if let Some(file) = &self.file {
+			// Failing to save history shouldn't get in the way of running commands
+			let _ = if append {
+				OpenOptions::new()
+					.create(true)
+					.append(true)
+					.open(file)
+					.and_then(|mut f| writeln!(f, "{line}"))
+			} else {
+				fs::write(file, self.entries.iter().map(|e| format!("{e}\n")).collect::<String>())
+			};
+		}
+	}
+
+	pub fn entries(&self) -> &[String] {
+		&self.entries
+	}
+
+	/// Forget every entry. As in bash, the history file is left alone.
+	pub fn clear(&mut self) {
+		self.entries.clear();
+	}
+
+	/// Find the entry an event designator (the text after `!`) refers to.
+	fn event(&self, designator: &str) -> Option<&str> {
+		let entry = if designator == "!" {
+			self.entries.last()
+		} else if let Ok(n) = designator.parse::<i64>() {
+			// Entries are numbered from 1; negative numbers count back from the end
+			let index = if n < 0 { self.entries.len() as i64 + n } else { n - 1 };
+			usize::try_from(index).ok().and_then(|i| self.entries.get(i))
+		} else {
+			self.entries.iter().rev().find(|entry| entry.starts_with(designator))
+		};
+		entry.map(String::as_str)
+	}
+
+	/// History expansion: `!!` becomes the previous command, `!N` entry N,
+	/// `!-N` the Nth previous command and `!prefix` the latest command starting
+	/// with prefix. Returns None when there was nothing to expand.
+	pub fn expand(&self, line: &str) -> Result<Option<String>, String> {
+		let mut expanded = String::new();
+		let mut changed = false;
+		let mut quote: Option<char> = None;
+		let mut chars = line.chars().peekable();
+
+		while let Some(ch) = chars.next() {
+			match ch {
+				'\'' | '"' if quote.is_none() => quote = Some(ch),
+				'\'' | '"' if quote == Some(ch) => quote = None,
+				'\\' if quote != Some('\'') => {
+					expanded.push(ch);
+					expanded.extend(chars.next());
+					continue;
+				},
+				// `$!` is a parameter, and `!` before a blank, `=` or `(` is literal
+				'!' if quote != Some('\'')
+					&& !expanded.ends_with('$')
+					&& chars.peek().is_some_and(|&c| !c.is_whitespace() && !matches!(c, '=' | '(' | '"')) =>
+				{
+					let mut designator = String::new();
+					if chars.next_if_eq(&'!').is_some() {
+						designator.push('!');
+					} else {
+						designator.extend(chars.next_if_eq(&'-'));
+						while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !";&|()<>'\"".contains(*c)) {
+							designator.push(c);
+						}
+					}
+					let entry = self.event(&designator).ok_or_else(|| format!("!{designator}: event not found"))?;
+					expanded.push_str(entry);
+					changed = true;
+					continue;
+				},
+				_ => {},
+			}
+			expanded.push(ch);
+		}
+
+		Ok(changed.then_some(expanded))
+	}
+}
@@ -0,0 +1,150 @@
+//! The job table: commands running in the background, or stopped with
+//! Ctrl-Z so they can be resumed later.
+
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+use crate::sys::{self, Pid};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum JobState {
+	Running,
+	Stopped,
+	Done(ExitStatus), // Finished, but not yet reported
+}
+
+#[derive(Clone)]
+pub struct Job {
+	pub id: usize, // The N in `%N`
+	pub pid: Pid,
+	pub command: String,
+	pub state: JobState,
+	pub no_hup: bool, // Set by `disown -h`: left alone when the shell is hung up on
+}
+
+#[derive(Clone, Default)]
+pub struct JobTable {
+	jobs: Vec<Job>, // In the order they were added; the last is the current job
+	disowned: Vec<Pid>, // Processes of disowned jobs, still to be reaped when they finish
+}
+
+impl JobTable {
+	/// Add a job, numbered one past the highest number in use, like bash.
+	/// Returns its number.
+	pub fn add(&mut self, pid: Pid, command: String, state: JobState) -> usize {
+		let id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+		self.jobs.push(Job { id, pid, command, state, no_hup: false });
+		id
+	}
+
+	pub fn get(&self, id: usize) -> Option<&Job> {
+		self.jobs.iter().find(|job| job.id == id)
+	}
+
+	pub fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+		self.jobs.iter_mut().find(|job| job.id == id)
+	}
+
+	/// The job whose process is `pid`.
+	pub fn with_pid(&self, pid: Pid) -> Option<&Job> {
+		self.jobs.iter().find(|job| job.pid == pid)
+	}
+
+	/// The jobs, oldest first.
+	pub fn iter(&self) -> impl Iterator<Item = &Job> {
+		self.jobs.iter()
+	}
+
+	/// Forget a job, once it has finished.
+	pub fn remove(&mut self, id: usize) -> Option<Job> {
+		let index = self.jobs.iter().position(|job| job.id == id)?;
+		Some(self.jobs.remove(index))
+	}
+
+	/// The job a jobspec such as `%2` refers to: `%%` or `%+` is the current
+	/// job, `%-` the previous one, `%N` job N, `%name` the one whose command
+	/// starts with `name` and `%?text` the one whose command contains `text`.
+	/// The error says why there isn't exactly one.
+	pub fn find(&self, spec: &str) -> Result<&Job, String> {
+		let no_such_job = || format!("{spec}: no such job");
+		let rest = spec.strip_prefix('%').unwrap_or(spec);
+		match rest {
+			"" | "%" | "+" => self.jobs.last().ok_or_else(no_such_job),
+			"-" => self.jobs.iter().rev().nth(1).or(self.jobs.last()).ok_or_else(no_such_job),
+			_ if rest.bytes().all(|b| b.is_ascii_digit()) => rest.parse().ok().and_then(|id| self.get(id)).ok_or_else(no_such_job),
+			_ => {
+				let mut matching = self.jobs.iter().filter(|job| match rest.strip_prefix('?') {
+					Some(text) => job.command.contains(text),
+					None => job.command.starts_with(rest),
+				});
+				match (matching.next(), matching.next()) {
+					(Some(job), None) => Ok(job),
+					(Some(_), Some(_)) => Err(format!("{spec}: ambiguous job spec")),
+					(None, _) => Err(no_such_job()),
+				}
+			},
+		}
+	}
+
+	/// Stop keeping track of a job, though its process is still reaped once
+	/// it finishes.
+	pub fn disown(&mut self, id: usize) {
+		if let Some(job) = self.remove(id) {
+			self.disowned.push(job.pid);
+		}
+	}
+
+	/// Note the jobs that have finished or stopped since they were last
+	/// checked, without waiting for any, and reap finished disowned ones.
+	pub fn update(&mut self) {
+		self.disowned.retain(|&pid| matches!(sys::poll_child(pid), Ok(None)));
+		for job in self.jobs.iter_mut().filter(|job| job.state == JobState::Running) {
+			match sys::poll_child(job.pid) {
+				Ok(Some(status)) if status.stopped_signal().is_some() => job.state = JobState::Stopped,
+				Ok(Some(status)) => job.state = JobState::Done(status),
+				Ok(None) => {},
+				// Someone else reaped it, e.g. `wait`; all we know is that it's gone
+				Err(_) => job.state = JobState::Done(ExitStatus::from_raw(0)),
+			}
+		}
+	}
+
+	/// Forget the finished jobs, returning their status lines to report.
+	pub fn take_finished(&mut self) -> Vec<String> {
+		let finished: Vec<String> = self.jobs
+			.iter()
+			.filter(|job| matches!(job.state, JobState::Done(_)))
+			.map(|job| self.describe(job, false))
+			.collect();
+		self.jobs.retain(|job| !matches!(job.state, JobState::Done(_)));
+		finished
+	}
+
+	/// The job's status line, e.g. `[1]+  Stopped                 sleep 10`,
+	/// with its pid after the number when `show_pid`. `+` marks the current
+	/// job and `-` the previous one.
+	pub fn describe(&self, job: &Job, show_pid: bool) -> String {
+		let marker = self.marker(job);
+		let state = match job.state {
+			JobState::Running => "Running".to_owned(),
+			JobState::Stopped => "Stopped".to_owned(),
+			JobState::Done(status) => match status.code() {
+				Some(0) => "Done".to_owned(),
+				Some(code) => format!("Exit {code}"),
+				_ => sys::termination_message(status).unwrap_or_else(|| "Done".to_owned()),
+			},
+		};
+		let pid = if show_pid { format!("{} ", job.pid) } else { String::new() };
+		let background = if job.state == JobState::Running { " &" } else { "" };
+		format!("[{}]{marker}  {pid}{state:<24}{}{background}", job.id, job.command)
+	}
+
+	/// `+` for the current job, `-` for the previous one, otherwise a space.
+	pub fn marker(&self, job: &Job) -> char {
+		match self.jobs.iter().rev().position(|j| j.id == job.id) {
+			Some(0) => '+',
+			Some(1) => '-',
+			_ => ' ',
+		}
+	}
+}
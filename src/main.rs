@@ -1,11 +1,42 @@
+mod arith;
+mod cli;
+mod complete;
+mod condition;
+mod diagnostics;
+mod editor;
+mod expand;
+mod highlight;
+mod history;
+mod jobs;
+mod options;
+mod path_index;
+mod printf;
+mod prompt;
+mod read;
+mod sys;
+mod unicode;
+mod vars;
+
 #[allow(unused_imports)]
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Write};
 use std::{env, fs};
-use std::collections::HashMap;
-use std::process::{Command, Stdio};
-use std::path::{Path, PathBuf};
+use std::collections::{HashMap, HashSet};
+use std::process::{Command, ExitStatus, Stdio};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
 use std::error::Error;
 use std::fs::{File, OpenOptions};
+use std::os::fd::{AsRawFd, IntoRawFd, OwnedFd};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+
+use editor::{EditingMode, LineEditor};
+use complete::CompletionSpec;
+use expand::{expand_command, scan_backticks, scan_command_substitution};
+use history::History;
+use jobs::{JobState, JobTable};
+use path_index::PathIndex;
+use vars::Variables;
 
 #[derive(PartialEq)]
 enum TokenizerState {
@@ -14,75 +45,280 @@ enum TokenizerState {
 	BackSlashInDoubleQuote,
 	Out, // Outside of quotes
 	BackSlashOutsideQuote, // Outside of quotes, but a backslash was encountered
+	InAnsiCQuote, // Inside $'...', where backslash escapes like \n are interpreted
+	InHereDoc, // In a here-document body, before the line with its delimiter
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+	Word(String),
+	AndIf,   // &&
+	OrIf,    // ||
+	Semi,    // ;
+	DSemi,   // ;;
+	Pipe,    // |
+	Amp,     // &, which runs the commands before it in the background
+	Newline, // Ends a command like `;`, but may also just end a line inside `{ ... }`
+	LParen,  // (
+	RParen,  // )
+	HereDocBody(String), // Lines following a line with `<<`, one per here-document, in order
+}
+
+/// Split the input line into words and operators.
+/// Words are kept raw, quotes and backslashes included, so that expansion
+/// can later tell quoted text apart from unquoted text.
+fn tokenize_input(input: &str) -> Result<Vec<Token>, TokenizerState> {
+	tokenize(input, false)
+}
+
+/// Tokenize and parse input that can't continue on another line. Like in
+/// bash, a here-document still open at the end is cut short there.
+fn parse_to_end(input: &str, aliases: &HashMap<String, String>) -> Result<CommandList, ParseError> {
+	tokenize(input, true).map_err(|_| ParseError::Incomplete).and_then(|tokens| parse_command_list(tokens, aliases))
+}
+
+/// The here-documents opened on the last line of `tokens`, as (delimiter,
+/// whether to strip leading tabs) pairs in the order they appear.
+fn heredoc_delimiters(tokens: &[Token]) -> Vec<(String, bool)> {
+	let line_start = tokens.iter().rposition(|t| *t == Token::Newline).map_or(0, |i| i + 1);
+	tokens[line_start..]
+		.windows(2)
+		.filter_map(|pair| match pair {
+			[Token::Word(op), Token::Word(word)] if matches!(parse_redirect_op(op), Some((_, RedirectMode::HereDoc { .. }))) => {
+				// Quotes around the delimiter are not part of it
+				Some((word.replace(['\'', '"', '\\'], ""), op.ends_with('-')))
+			},
+			_ => None,
+		})
+		.collect()
+}
+
+/// Take a here-document body from `chars`: the lines up to the one equal to
+/// `delimiter`, which is consumed too. With `strip_tabs` (`<<-`), leading tabs
+/// are removed from every line first. Running out of input is an error
+/// unless `at_end` says no more is coming.
+fn take_heredoc_body(chars: &mut std::iter::Peekable<std::str::Chars>, delimiter: &str, strip_tabs: bool, at_end: bool) -> Result<String, TokenizerState> {
+	let mut body = String::new();
+	loop {
+		if chars.peek().is_none() {
+			if !at_end {
+				return Err(TokenizerState::InHereDoc);
+			}
+			diagnostics::warning(format_args!("here-document delimited by end-of-file (wanted `{delimiter}')"));
+			return Ok(body);
+		}
+
+		let line: String = chars.by_ref().take_while(|&c| c != '\n').collect();
+		let line = if strip_tabs { line.trim_start_matches('\t') } else { &line };
+		if line == delimiter {
+			return Ok(body);
+		}
+		body.push_str(line);
+		body.push('\n');
+	}
 }
 
-fn tokenize_input(input: &str) -> Vec<String> {
+fn tokenize(input: &str, at_end: bool) -> Result<Vec<Token>, TokenizerState> {
 	let mut tokens = Vec::new();
 	let mut current_token = String::new();
 	let mut state = TokenizerState::Out;
+	let mut chars = input.chars().peekable();
 
-	for ch in input.chars() {
+	while let Some(ch) = chars.next() {
 		match (&state, ch) {
 			(TokenizerState::Out, '\"') => {
 				state = TokenizerState::InDoubleQuote;
+				current_token.push(ch);
 			},
 			
 			(TokenizerState::Out, '\'') => {
 				state = TokenizerState::InSingleQuote;
+				current_token.push(ch);
 			},
 
 			(TokenizerState::InSingleQuote, '\'') => {
 				state = TokenizerState::Out;
+				current_token.push(ch);
 			},
 
 			(TokenizerState::InDoubleQuote, '\"') => {
 				state = TokenizerState::Out;
+				current_token.push(ch);
 			},
 
 			(TokenizerState::Out, char) => {
-				if char.is_whitespace() { // If we encounter whitespace, we finalize the current token
+				if char == '\n' || char == '(' || char == ')' {
 					if !current_token.is_empty() {
-						tokens.push(current_token.clone());
+						tokens.push(Token::Word(current_token.clone()));
+						current_token.clear();
+					}
+					let heredocs = if char == '\n' { heredoc_delimiters(&tokens) } else { Vec::new() };
+					tokens.push(match char {
+						'\n' => Token::Newline,
+						'(' => Token::LParen,
+						_ => Token::RParen,
+					});
+					// Here-document bodies start on the line after their `<<`
+					for (delimiter, strip_tabs) in heredocs {
+						tokens.push(Token::HereDocBody(take_heredoc_body(&mut chars, &delimiter, strip_tabs, at_end)?));
+					}
+				}
+				else if char.is_whitespace() { // If we encounter whitespace, we finalize the current token
+					if !current_token.is_empty() {
+						tokens.push(Token::Word(current_token.clone()));
 						current_token.clear();
 					}
 				} 
-				else if char == '\\' {
-					state = TokenizerState::BackSlashOutsideQuote; // If we encounter a backslash, we change the state
-					continue; // Skip adding the backslash to the current token
-				} 
+				else if (char == '&' || char == '|') && chars.peek() == Some(&char) {
+					// `&&` and `||` are operators even without surrounding whitespace
+					chars.next();
+					if !current_token.is_empty() {
+						tokens.push(Token::Word(current_token.clone()));
+						current_token.clear();
+					}
+					tokens.push(if char == '&' { Token::AndIf } else { Token::OrIf });
+				}
+				else if char == '&' {
+					// Unlike in `&> file`, a lone `&` ends a command
+					if !current_token.is_empty() {
+						tokens.push(Token::Word(current_token.clone()));
+						current_token.clear();
+					}
+					if chars.peek() == Some(&'>') {
+						current_token.push('&'); // Taken up with the `>` next
+					} else {
+						tokens.push(Token::Amp);
+					}
+				}
+				else if char == ';' || char == '|' {
+					if !current_token.is_empty() {
+						tokens.push(Token::Word(current_token.clone()));
+						current_token.clear();
+					}
+					tokens.push(match char {
+						'|' => Token::Pipe,
+						_ if chars.next_if_eq(&';').is_some() => Token::DSemi,
+						_ => Token::Semi,
+					});
+				}
+				else if char == '#' && current_token.is_empty() {
+					// A comment runs to the end of the line; `#` inside a word is literal
+					while chars.next_if(|&c| c != '\n').is_some() {}
+				}
+				else if char == '$' && chars.peek() == Some(&'\'') {
+					chars.next();
+					state = TokenizerState::InAnsiCQuote;
+					current_token.push_str("$'");
+				}
+				else if char == '$' && chars.peek() == Some(&'(') {
+					// Command substitution: copy it verbatim, operators and all
+					chars.next();
+					current_token.push_str("$(");
+					current_token.push_str(&scan_command_substitution(&mut chars));
+					current_token.push(')');
+				}
+				else if char == '<' && chars.peek() == Some(&'<') {
+					// `<<`, `<<-` and `<<<` are operators even when attached to the
+					// words around them, as in `cat<<EOF`; digits right before are
+					// the fd they redirect
+					if !current_token.chars().all(|c| c.is_ascii_digit()) {
+						tokens.push(Token::Word(std::mem::take(&mut current_token)));
+					}
+					chars.next();
+					current_token.push_str("<<");
+					current_token.extend(chars.next_if(|&c| c == '<' || c == '-'));
+					tokens.push(Token::Word(std::mem::take(&mut current_token)));
+				}
+				else if (char == '<' || char == '>') && chars.peek() == Some(&'(') {
+					// Process substitution, copied verbatim like `$(...)`
+					chars.next();
+					current_token.push(char);
+					current_token.push('(');
+					current_token.push_str(&scan_command_substitution(&mut chars));
+					current_token.push(')');
+				}
+				else if char == '<' || char == '>' {
+					// `>`, `>>`, `<`, `>&N` and `<&N` are operators even when attached
+					// to words too, as in `echo hi>file` or `cmd 2>&1`; digits right
+					// before are the fd, and a `&` right before makes `&>` or `&>>`
+					if !current_token.chars().all(|c| c.is_ascii_digit()) && current_token != "&" {
+						tokens.push(Token::Word(std::mem::take(&mut current_token)));
+					}
+					current_token.push(char);
+					if char == '>' {
+						current_token.extend(chars.next_if_eq(&'>'));
+					}
+					if !current_token.ends_with(">>") && !current_token.starts_with('&') {
+						if let Some(amp) = chars.next_if_eq(&'&') {
+							// The fd to duplicate belongs to the operator
+							current_token.push(amp);
+							while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+								current_token.push(digit);
+							}
+						}
+					}
+					tokens.push(Token::Word(std::mem::take(&mut current_token)));
+				}
+				else if char == '`' {
+					current_token.push('`');
+					current_token.push_str(&scan_backticks(&mut chars));
+					current_token.push('`');
+				}
 				else {
-					current_token.push(char); // Otherwise, we add the character to the current token
+					if char == '\\' {
+						state = TokenizerState::BackSlashOutsideQuote; // The next character is taken literally
+					}
+					current_token.push(char);
 				}
 			},
-			
-			(TokenizerState::BackSlashOutsideQuote, any) =>{
+
+			(TokenizerState::BackSlashOutsideQuote, any) => {
 				current_token.push(any);
 				state = TokenizerState::Out; // Return to the outside state after handling the backslash
 			}
 
-			(TokenizerState::InSingleQuote, any) => {
-				current_token.push(any); // In single quotes, we just add the character to the current token
+			(TokenizerState::InDoubleQuote, '$') if chars.peek() == Some(&'(') => {
+				chars.next();
+				current_token.push_str("$(");
+				current_token.push_str(&scan_command_substitution(&mut chars));
+				current_token.push(')');
+			},
+
+			(TokenizerState::InDoubleQuote, '`') => {
+				current_token.push('`');
+				current_token.push_str(&scan_backticks(&mut chars));
+				current_token.push('`');
 			},
 
 			(TokenizerState::InDoubleQuote, any) => {
 				if any == '\\' {
 					state = TokenizerState::BackSlashInDoubleQuote; // In double quotes, a backslash changes the state
-					continue; // Skip adding the backslash to the current token
 				}
-				current_token.push(any); // In double quotes, we just add the character to the current token
+				current_token.push(any);
 			},
 
 			(TokenizerState::BackSlashInDoubleQuote, any) => {
-				if any == '$' || any == '`' || any == '\\' || any == '"' || any == '\n'{
-					// In double quotes, we escape $, `, \ and " characters
-					current_token.push(any);
-				}
-				else {
-					current_token.push('\\');
-					current_token.push(any); // In double quotes, we just add the character to the current token
-				}
+				current_token.push(any);
 				state = TokenizerState::InDoubleQuote; // Return to double quote state
 			}
+
+			(TokenizerState::InSingleQuote, any) => {
+				current_token.push(any); // In single quotes, we just add the character to the current token
+			},
+
+			(TokenizerState::InAnsiCQuote, any) => {
+				current_token.push(any);
+				if any == '\\' {
+					// Whatever follows is escaped, even a quote
+					current_token.extend(chars.next());
+				} else if any == '\'' {
+					state = TokenizerState::Out;
+				}
+			},
+
+			// Bodies are taken whole at the end of their line, so it is only
+			// ever reported as where the input stopped
+			(TokenizerState::InHereDoc, _) => unreachable!(),
 		}
 	};
 
@@ -90,46 +326,236 @@ fn tokenize_input(input: &str) -> Vec<String> {
 	// This handles the case where the last token is not followed by whitespace
 	// or a closing quote
 	if !current_token.is_empty() {
-		tokens.push(current_token);
+		tokens.push(Token::Word(current_token));
 	}
 
-	tokens
+	// An unterminated quote or trailing backslash means the input continues on
+	// the next line, so report where we stopped
+	if state != TokenizerState::Out {
+		return Err(state);
+	}
+	// Likewise when a here-document was started on the last line
+	for (delimiter, _) in heredoc_delimiters(&tokens) {
+		if !at_end {
+			return Err(TokenizerState::InHereDoc);
+		}
+		diagnostics::warning(format_args!("here-document delimited by end-of-file (wanted `{delimiter}')"));
+		tokens.push(Token::HereDocBody(String::new()));
+	}
+
+	Ok(tokens)
 }
 
-#[derive(Debug)]
+/// Fork a copy of the shell that runs `source` and exits with its status.
+/// `setup` runs in the child first, to connect its stdin or stdout to a pipe.
+fn fork_subshell(source: &str, shell: &ShellState, setup: impl FnOnce() -> io::Result<()>) -> io::Result<sys::Pid> {
+	fork_shell(|| {
+		setup()?;
+		let mut shell = shell.clone();
+		shell.reset_traps();
+		shell.job_control = false; // Part of the command being expanded, not a job
+		let status = run_source(source, &mut shell)?;
+		exit_shell(&mut shell, status)
+	})
+}
+
+/// Fork a copy of the shell that calls `run` and exits with the status it returns.
+fn fork_shell(run: impl FnOnce() -> Result<i32, Box<dyn Error>>) -> io::Result<sys::Pid> {
+	io::stdout().flush()?; // Don't let the child inherit unwritten output
+
+	let pid = sys::fork_process()?;
+	if pid == 0 {
+		// Pipes from the parent's process substitutions belong to the parent.
+		// Holding a copy would keep their readers from ever seeing end-of-file
+		PROCESS_SUBSTITUTIONS.with(|subs| subs.borrow_mut().clear());
+
+		let status = run().unwrap_or_else(|e| {
+			diagnostics::error(e);
+			1
+		});
+		let _ = io::stdout().flush();
+		std::process::exit(status);
+	}
+	Ok(pid)
+}
+
+/// Run `source` in a forked copy of the shell and return what it wrote to
+/// stdout, minus trailing newlines. Being a separate process, the command
+/// can't change the parent's variables or working directory.
+fn capture_output(source: &str, shell: &ShellState) -> String {
+	let captured = (|| -> io::Result<String> {
+		let (read_end, write_end) = sys::pipe()?;
+		let pid = fork_subshell(source, shell, || {
+			sys::close_fd(read_end.as_raw_fd())?;
+			sys::dup_onto(write_end.as_raw_fd(), 1)
+		})?;
+
+		drop(write_end);
+		let mut output = String::new();
+		io::Read::read_to_string(&mut File::from(read_end), &mut output)?;
+		let status = exit_code(sys::wait_for(pid)?);
+		SUBSTITUTION_STATUS.with(|last| last.set(Some(status)));
+		Ok(output)
+	})();
+
+	match captured {
+		Ok(mut output) => {
+			output.truncate(output.trim_end_matches('\n').len());
+			output
+		},
+		Err(e) => {
+			diagnostics::error(format_args!("command substitution: {e}"));
+			String::new()
+		}
+	}
+}
+
+thread_local! {
+	/// Pipe ends and children of process substitutions used by the command
+	/// being run, released once it finishes
+	static PROCESS_SUBSTITUTIONS: std::cell::RefCell<Vec<(OwnedFd, sys::Pid)>> = const { std::cell::RefCell::new(Vec::new()) };
+
+	/// Status of the last command substitution in the command being expanded,
+	/// which becomes `$?` when there's no command word, as in `x=$(false)`
+	static SUBSTITUTION_STATUS: std::cell::Cell<Option<i32>> = const { std::cell::Cell::new(None) };
+}
+
+/// Start `source` connected to a pipe and return a `/dev/fd/N` path for the
+/// other end: `<(cmd)` reads the command's output, `>(cmd)` feeds its input.
+fn process_substitution(source: &str, shell: &ShellState, output: bool) -> io::Result<String> {
+	let (read_end, write_end) = sys::pipe()?;
+	let (ours, theirs, child_fd) = if output { (read_end, write_end, 1) } else { (write_end, read_end, 0) };
+
+	let pid = fork_subshell(source, shell, || {
+		sys::close_fd(ours.as_raw_fd())?;
+		sys::dup_onto(theirs.as_raw_fd(), child_fd)
+	})?;
+	drop(theirs);
+
+	// The path is opened by the command we are about to run, so it must
+	// survive exec
+	sys::set_inheritable(ours.as_raw_fd())?;
+	let path = format!("/dev/fd/{}", ours.as_raw_fd());
+	PROCESS_SUBSTITUTIONS.with(|subs| subs.borrow_mut().push((ours, pid)));
+	Ok(path)
+}
+
+/// Close the pipes handed out by process substitution and reap their children.
+fn finish_process_substitutions() {
+	let finished = PROCESS_SUBSTITUTIONS.with(|subs| std::mem::take(&mut *subs.borrow_mut()));
+	for (fd, pid) in finished {
+		drop(fd);
+		let _ = sys::wait_for(pid);
+	}
+}
+
+#[derive(Debug, Clone)]
 struct ParsedCommand {
+	assignments: Vec<(String, String)>, // NAME=value words, raw like argv
 	argv: Vec<String>, // Arguments for the command
-	redirects: HashMap<u8, Redirection> // Path to the file for redirection
+	redirects: Vec<Redirection> // Redirections in the order they were written
 }
 
-#[derive(Debug)]
+/// How a command in a list depends on the status of the one before it
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Connector {
+	Always, // first command of the list, or one following `;`
+	And,    // &&, run only if the previous command succeeded
+	Or,     // ||, run only if the previous command failed
+}
+
+#[derive(Debug, Clone)]
 struct Redirection {
 	fd: u8, // Fd destination, e.g., 1 for stdout (1<file means file is stored in fd 1)
-	mode: RedirectMode, // Whether to append to the file (true) or overwrite it (false)
-	path: PathBuf, // Path to the file for redirection
+	mode: RedirectMode, // How the destination is opened (or which fd it duplicates)
+	path: PathBuf, // Path to the file for redirection, empty for fd duplication
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum RedirectMode {
     Truncate,   // >
     Append,     // >>
+    Read,       // <
+    DupFd(u8),  // N>&M or N<&M, the source fd M to duplicate
+    HereDoc {   // << or <<-, the body comes from the lines after the command's
+        expand: bool, // false when the delimiter was quoted
+        body: String,
+    },
+    HereString(String), // <<<, the word itself (plus a newline) becomes stdin
+}
+
+/// Parse a redirection operator such as `>`, `2>>` or `4<` into (fd, mode).
+/// The fd defaults to 1 for output and 0 for input when no number is given.
+fn parse_redirect_op(token: &str) -> Option<(u8, RedirectMode)> {
+	let op_start = token.find(|c: char| !c.is_ascii_digit())?;
+	let (digits, op) = token.split_at(op_start);
+	let (default_fd, mode) = match op {
+		">"  => (1, RedirectMode::Truncate),
+		">>" => (1, RedirectMode::Append),
+		"<"  => (0, RedirectMode::Read),
+		"<<" | "<<-" => (0, RedirectMode::HereDoc {
+			expand: true,
+			body: String::new(),
+		}),
+		"<<<" => (0, RedirectMode::HereString(String::new())),
+		_ => return None,
+	};
+	let fd = if digits.is_empty() { default_fd } else { digits.parse().ok()? };
+	Some((fd, mode))
+}
+
+/// Parse a duplication operator such as `2>&1`, `>&2` or `3<&0` into (fd, source fd).
+fn parse_dup_token(token: &str) -> Option<(u8, u8)> {
+	let (lhs, rhs, default_fd) = if let Some((lhs, rhs)) = token.split_once(">&") {
+		(lhs, rhs, 1)
+	} else {
+		let (lhs, rhs) = token.split_once("<&")?;
+		(lhs, rhs, 0)
+	};
+	let fd = if lhs.is_empty() { default_fd } else { lhs.parse().ok()? };
+	let source = rhs.parse().ok()?;
+	Some((fd, source))
 }
 
 
 fn new_token_parser(tokens: Vec<String>)-> Result<ParsedCommand, Box<dyn Error>> {
 	let mut argv: Vec<String> = Vec::new();
 	let mut pending: Option<(u8, RedirectMode)> = Option::None;
-	let mut redirects: HashMap<u8, Redirection> = HashMap::new();
+	let mut pending_both = false; // `&>` and `&>>` also send stderr to the same file
+	let mut redirects: Vec<Redirection> = Vec::new();
 
 	for token in tokens {
+		if pending.is_none() {
+			if let Some(op) = parse_redirect_op(&token) {
+				pending = Some(op);
+				continue;
+			}
+		}
+
 		match token.as_str() {
-			">"  | "1>" => pending = Some((1, RedirectMode::Truncate)),
-			">>" | "1>>"=> pending = Some((1, RedirectMode::Append)),
-			"2>"       => pending = Some((2, RedirectMode::Truncate)),
-			"2>>"      => pending = Some((2, RedirectMode::Append)),
+			"&>"       => { pending = Some((1, RedirectMode::Truncate)); pending_both = true; },
+			"&>>"      => { pending = Some((1, RedirectMode::Append)); pending_both = true; },
 			_ => {
-				if let Some((fd, mode)) = pending.take() {
-					redirects.insert(fd, Redirection { fd, mode, path: token.into() });
+				if let Some((fd, mut mode)) = pending.take() {
+					// The word after `<<` is the delimiter and the word after `<<<`
+					// is the text itself; for every other operator it names a file
+					let path = match &mut mode {
+						RedirectMode::HereDoc { expand, .. } => {
+							// Quoting the delimiter turns off expansion inside the body
+							*expand = !token.contains(['\'', '"', '\\']);
+							PathBuf::new()
+						},
+						RedirectMode::HereString(text) => { *text = token; PathBuf::new() },
+						_ => token.into(),
+					};
+					redirects.push(Redirection { fd, mode, path });
+					if pending_both {
+						// Same as `> file 2>&1`: stderr shares the handle opened for stdout
+						redirects.push(Redirection { fd: 2, mode: RedirectMode::DupFd(1), path: PathBuf::new() });
+						pending_both = false;
+					}
+				} else if let Some((fd, source)) = parse_dup_token(&token) {
+					redirects.push(Redirection { fd, mode: RedirectMode::DupFd(source), path: PathBuf::new() });
 				} else {
 					argv.push(token);
 				}
@@ -141,202 +567,3215 @@ fn new_token_parser(tokens: Vec<String>)-> Result<ParsedCommand, Box<dyn Error>>
         return Err("syntax error: redirection without file".into());
     }
 
-    Ok(ParsedCommand { argv, redirects })
+	// Leading NAME=value words are assignments: on their own they set shell
+	// variables, before a command they only affect that command's environment
+	let prefix_len = argv.iter().take_while(|word| parse_assignment(word).is_some()).count();
+	let assignments = argv.drain(..prefix_len).filter_map(|word| parse_assignment(&word)).collect();
+
+    Ok(ParsedCommand { assignments, argv, redirects })
 }
 
 
-fn open_redir(redir: &Redirection) -> std::io::Result<fs::File> {
-    
-    match redir.mode {
-        RedirectMode::Truncate => File::create(&redir.path),
-        RedirectMode::Append   => OpenOptions::new()
-                                       .create(true)
-                                       .append(true)
-                                       .open(&redir.path),
-    }
+/// Split a raw `NAME=value` word into its name and (still unexpanded) value.
+fn parse_assignment(word: &str) -> Option<(String, String)> {
+	let (name, value) = word.split_once('=')?;
+	vars::is_valid_name(name).then(|| (name.to_owned(), value.to_owned()))
 }
 
-/// Return a boxed writer that is either the redirection file
-/// or Stdout when no redirection was requested.
-fn writer_for_fd(redirects: &HashMap<u8, Redirection>, fd: u8) -> std::io::Result<Box<dyn std::io::Write>> {
-    if let Some(r) = redirects.get(&fd) { // If there is a redirection for this fd
-    	Ok(Box::new(open_redir(r)?))
-	} else {
-		match fd {
-			1 => Ok(Box::new(io::stdout())),
-			2 => Ok(Box::new(io::stderr())),
-			_ => Err(io::Error::new(
-				io::ErrorKind::Other,
-				format!("unsupported fd {fd}"),
-			)),
-		}
-	}
-}
+/// Commands joined by `;`, newlines, `&&` and `||`, each with how it
+/// depends on the one before
+type CommandList = Vec<(Connector, ShellCommand)>;
 
-fn main() -> Result<(), Box<dyn Error>> {
-	// Define the built-in commands for this shell
-	static BUILTIN_COMMANDS: [&str; 4] = ["type", "echo", "exit", "pwd"];
-
-	// Build an index of *external* commands once at start-up
-	let val = env::var("PATH")?; // this panics if PATH is not set, in which case what's the point?
-	let paths: Vec<&str> = val
-		.split(':')
-		.filter(|x| !x.contains("/mnt/c"))
-		.filter(|x| !x.contains("/home/admin/.vscode-server"))
-		.collect();
+/// A command as written, before any expansion
+#[derive(Debug, Clone)]
+enum ShellCommand {
+	Simple(ParsedCommand),
+	FunctionDef(String, CommandList), // `name() { body; }`, which defines `name` rather than running anything
+	If {
+		branches: Vec<(CommandList, CommandList)>, // Condition and body of the `if` and each `elif`
+		otherwise: Option<CommandList>, // The `else` body
+	},
+	For {
+		name: String, // The loop variable
+		words: Option<Vec<String>>, // Raw words after `in`; without `in` the loop goes over "$@"
+		body: CommandList,
+	},
+	While {
+		until: bool, // `until` loops while the condition fails
+		condition: CommandList,
+		body: CommandList,
+	},
+	Case {
+		word: String, // The raw word matched against
+		clauses: Vec<(Vec<String>, CommandList)>, // Raw patterns, and the body run when one matches
+	},
+	Subshell(CommandList), // `( ... )`, run in a forked copy of the shell
+	Background(CommandList), // Commands ending in `&`, run in a forked copy of the shell without waiting
+	Group(CommandList), // `{ ...; }`, run in this shell
+	Redirected(Box<ShellCommand>, Vec<Redirection>), // A compound command followed by redirections
+	Not(Box<ShellCommand>), // `! command`, which inverts the status
+	Pipeline(Vec<ShellCommand>), // `a | b | ...`, each command's stdout feeding the next one's stdin
+	Conditional(condition::Conditional), // `[[ expression ]]`
+}
 
-	let path_commands: HashMap<String, PathBuf> = paths
-		.into_iter()
-		.flat_map(|dir| {
-			fs::read_dir(dir)
-				.ok()
-				.into_iter()
-				.flatten()
-				.filter_map(Result::ok)
-				.filter_map(|e| {
-					if !e.file_type().map_or(false, |ft| ft.is_file()) {
-						// Only consider files, skip directories and other types
-						// Also skip if the filetype cannot be determined
-						return None;
-					}
-
-					let p = e.path();
-					let name = match p.file_name().and_then(|n| n.to_str()) {
-						Some(s) => s.to_owned(),
-						None => return None,
-					};
-					Some((name, p)) 
-				})
-		})
-		.fold(HashMap::new(), |mut acc, (name, path)| {
-			acc.entry(name).or_insert(path);
-			acc
-		});
+impl ShellCommand {
+	/// The command lists nested in this command, in the order they were written.
+	fn lists_mut(&mut self) -> Vec<&mut CommandList> {
+		match self {
+			ShellCommand::Simple(_) | ShellCommand::Conditional(_) => Vec::new(),
+			ShellCommand::FunctionDef(_, body)
+			| ShellCommand::For { body, .. }
+			| ShellCommand::Subshell(body)
+			| ShellCommand::Background(body)
+			| ShellCommand::Group(body) => vec![body],
+			ShellCommand::Redirected(command, _) | ShellCommand::Not(command) => command.lists_mut(),
+			ShellCommand::Pipeline(stages) => stages.iter_mut().flat_map(|stage| stage.lists_mut()).collect(),
+			ShellCommand::While { condition, body, .. } => vec![condition, body],
+			ShellCommand::Case { clauses, .. } => clauses.iter_mut().map(|(_, body)| body).collect(),
+			ShellCommand::If { branches, otherwise } => branches
+				.iter_mut()
+				.flat_map(|(condition, body)| [condition, body])
+				.chain(otherwise)
+				.collect(),
+		}
+	}
 
-	// Wait for user input
-    loop {
-		// Prompt the user for input
-		print!("$ ");
-		io::stdout().flush().unwrap();
+	/// The redirections written on this command itself, rather than on the
+	/// commands nested in it.
+	fn redirects_mut(&mut self) -> Option<&mut Vec<Redirection>> {
+		match self {
+			ShellCommand::Simple(simple) => Some(&mut simple.redirects),
+			ShellCommand::Redirected(_, redirects) => Some(redirects),
+			ShellCommand::Not(command) => command.redirects_mut(),
+			_ => None,
+		}
+	}
+}
 
-		// Read a line of input
-        let mut input = String::new();
-        io::stdin().read_line(&mut input).unwrap();
-		
-		let tokens = tokenize_input(input.trim());
+/// Why tokens couldn't be parsed into commands
+#[derive(Debug)]
+enum ParseError {
+	Incomplete, // The input stopped inside a construct, e.g. after `&&` or before a closing `}`
+	Syntax(String),
+}
 
-		if tokens.is_empty() {
-			// If no tokens were found, prompt again
-			continue;
+impl std::fmt::Display for ParseError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			ParseError::Incomplete => write!(f, "syntax error: unexpected end of file"),
+			ParseError::Syntax(message) => write!(f, "{message}"),
 		}
+	}
+}
 
-		let ParsedCommand { argv, redirects } = match new_token_parser(tokens) {
-			Ok(p) => p,
-			Err(e) => {
-				eprintln!("{e}");
-				continue;
-			}
-		};
+impl Error for ParseError {}
 
-		let mut argv = argv.iter().map(|x| x.as_str());
-		let cmd = argv.next().unwrap(); // can unwrap safely because we already checked that tokens is not empty
+type Tokens = std::iter::Peekable<std::vec::IntoIter<Token>>;
 
-		// Validate input
-		match cmd {
-			"type" => {
-				let Some(query) = argv.next() else {    // no argument after `type`
-					let mut err_out = writer_for_fd(&redirects, 2)?;
-					writeln!(err_out, "type: missing operand")?;
-					continue;
-				};
+/// Reserved words that end a construct, so can't start a command
+const CLOSING_WORDS: [&str; 8] = ["then", "elif", "else", "fi", "do", "done", "esac", "}"];
 
-				let mut out = writer_for_fd(&redirects, 1)?;
+/// The error for finding `token` where it can't go; running out of tokens
+/// just means the command continues on the next line.
+fn unexpected(token: Option<&Token>) -> ParseError {
+	let text = match token {
+		None => return ParseError::Incomplete,
+		Some(Token::Word(word)) => word,
+		Some(Token::AndIf) => "&&",
+		Some(Token::OrIf) => "||",
+		Some(Token::Semi) => ";",
+		Some(Token::DSemi) => ";;",
+		Some(Token::Pipe) => "|",
+		Some(Token::Amp) => "&",
+		Some(Token::Newline | Token::HereDocBody(_)) => "newline",
+		Some(Token::LParen) => "(",
+		Some(Token::RParen) => ")",
+	};
+	ParseError::Syntax(format!("syntax error near unexpected token `{text}'"))
+}
 
-				let msg = if BUILTIN_COMMANDS.contains(&query) {
-					format!("{query} is a shell builtin")
-				} else if let Some(path) = path_commands.get(query) {
-					format!("{query} is {}", path.display())
-				} else {
-					format!("{query}: not found")
-				};
+/// Parse a whole command line, or several lines of a script, expanding
+/// `aliases` first.
+fn parse_command_list(tokens: Vec<Token>, aliases: &HashMap<String, String>) -> Result<CommandList, ParseError> {
+	let tokens = expand_aliases(tokens, aliases);
+	let (bodies, tokens): (Vec<Token>, Vec<Token>) = tokens.into_iter().partition(|t| matches!(t, Token::HereDocBody(_)));
+	let mut tokens = tokens.into_iter().peekable();
+	let mut commands = parse_list(&mut tokens, &[])?;
+	if let Some(token) = tokens.peek() {
+		return Err(unexpected(Some(token)));
+	}
 
-				writeln!(out, "{msg}")?;
-			}
+	// Here-document bodies come in the same order as the `<<`s they belong to
+	let mut bodies = bodies.into_iter().map(|token| match token {
+		Token::HereDocBody(body) => body,
+		_ => String::new(),
+	});
+	fill_heredocs(&mut commands, &mut bodies);
+	Ok(commands)
+}
 
-			"echo" => {
-				let mut out = writer_for_fd(&redirects, 1)?;
-				let _ = writer_for_fd(&redirects, 2)?;
+/// Reserved words after which a command starts, so an alias can follow them.
+const COMMAND_PREFIXES: [&str; 10] = ["if", "then", "else", "elif", "do", "while", "until", "{", "!", "time"];
 
-    			writeln!(out, "{}", argv.collect::<Vec<&str>>().join(" "))?;
+/// Replace words naming aliases at the start of commands with the tokens of
+/// their values. An alias met again while its own value is being expanded,
+/// as in `alias ls='ls -F'`, stays a plain word, and a value ending in a
+/// blank makes the word after it a candidate too. `case` patterns are never
+/// commands, so they are left alone.
+fn expand_aliases(tokens: Vec<Token>, aliases: &HashMap<String, String>) -> Vec<Token> {
+	if aliases.is_empty() {
+		return tokens;
+	}
+	let mut expanded = Vec::with_capacity(tokens.len());
+	let mut at_command = true; // Whether the next word starts a command
+	let mut case_words = 0; // Words left up to the `in` of a `case`
+	let mut in_patterns = false; // Whether the words are `case` patterns
+	for token in tokens {
+		match token {
+			Token::Word(word) if in_patterns => {
+				in_patterns = word != "esac";
+				at_command = false;
+				expanded.push(Token::Word(word));
 			},
-
-			"exit" => {
-				if argv.next() == Some("0") {std::process::exit(0)} 
-				else {
-					println!("Did you mean `exit 0`?");
-					continue
+			Token::Word(word) if at_command => {
+				// Assignments like `FOO=1` still leave room for a command
+				at_command = COMMAND_PREFIXES.contains(&word.as_str()) || parse_assignment(&word).is_some();
+				if word == "case" {
+					case_words = 2;
+				}
+				match alias_tokens(&word, aliases, &mut Vec::new()) {
+					Some((tokens, blank)) => {
+						expanded.extend(tokens);
+						at_command = blank;
+					},
+					None => expanded.push(Token::Word(word)),
 				}
 			},
-
-			"pwd" => {
-				match env::current_dir() {
-					Ok(path) => {
-						let mut out = writer_for_fd(&redirects, 1)?;
-							writeln!(out, "{}", path.display())?;
-					}
-					Err(e) => {
-						let mut err_out = writer_for_fd(&redirects, 2)?;
-						writeln!(err_out, "pwd: {e}")?;
-					}
+			Token::Word(word) => {
+				if case_words > 0 {
+					case_words -= 1;
+					in_patterns = case_words == 0 && word == "in";
 				}
+				expanded.push(Token::Word(word));
+			},
+			Token::Semi | Token::Newline | Token::AndIf | Token::OrIf | Token::Pipe | Token::Amp | Token::LParen => {
+				at_command = !in_patterns;
+				expanded.push(token);
 			},
+			Token::RParen => {
+				// The `)` ending a pattern comes before the commands for it
+				at_command = in_patterns;
+				in_patterns = false;
+				expanded.push(token);
+			},
+			Token::DSemi => {
+				in_patterns = true;
+				expanded.push(token);
+			},
+			Token::HereDocBody(_) => expanded.push(token),
+		}
+	}
+	expanded
+}
 
-			"cd" => {
-				// If no argument is given, change to the home directory,
-				// or to the root directory if HOME is not set
-				let fallback = env::var("HOME").unwrap_or_else(|_| "/".to_owned());
-				let query = 
-				match argv.next() {
-					Some("~") => fallback, 
-					Some(q) => q.to_owned(),
-					None => fallback
-				};
-				
-				let dir = Path::new(&query).canonicalize();
-				match dir {
-					Err(_) => eprintln!("cd: {query}: No such file or directory"),
-					Ok(path) => env::set_current_dir(path).unwrap()
+/// The tokens the alias `word` stands for, with whether its value ends in a
+/// blank, or None when it isn't an alias or is already being expanded.
+fn alias_tokens(word: &str, aliases: &HashMap<String, String>, expanding: &mut Vec<String>) -> Option<(Vec<Token>, bool)> {
+	if expanding.iter().any(|name| name == word) {
+		return None;
+	}
+	let value = aliases.get(word)?;
+	let mut tokens = tokenize(value, true).ok()?;
+	expanding.push(word.to_owned());
+	// The value's first word may be an alias in turn
+	if let Some(Token::Word(first)) = tokens.first() {
+		if let Some((inner, _)) = alias_tokens(&first.clone(), aliases, expanding) {
+			tokens.splice(..1, inner);
+		}
+	}
+	Some((tokens, value.ends_with([' ', '\t'])))
+}
+
+/// Hand out here-document bodies to the redirections they belong to.
+fn fill_heredocs(commands: &mut CommandList, bodies: &mut impl Iterator<Item = String>) {
+	for (_, command) in commands {
+		fill_command_heredocs(command, bodies);
+	}
+}
+
+/// Hand out here-document bodies to the redirections of `command` and of the
+/// commands in it.
+fn fill_command_heredocs(command: &mut ShellCommand, bodies: &mut impl Iterator<Item = String>) {
+	match command {
+		ShellCommand::Not(command) => fill_command_heredocs(command, bodies),
+		ShellCommand::Pipeline(stages) => {
+			for stage in stages {
+				fill_command_heredocs(stage, bodies);
+			}
+		},
+		_ => {
+			// A compound command's own redirections come after everything inside it
+			for list in command.lists_mut() {
+				fill_heredocs(list, bodies);
+			}
+			for redir in command.redirects_mut().into_iter().flatten() {
+				if let RedirectMode::HereDoc { body, .. } = &mut redir.mode {
+					*body = bodies.next().unwrap_or_default();
 				}
-			},
+			}
+		},
+	}
+}
 
-			// Handle external commands, i.e., commands not in the built-in list
-			_ => {
-				if let Some(_) = path_commands.get(cmd) {
-					let mut child = Command::new(cmd);
-
-					child.args(argv)                     
-						.stdin(Stdio::inherit()) 
-						.stderr(Stdio::inherit());
-					
-					for redir in redirects.values() {
-						let file = open_redir(redir)?;
-
-						// Match the file descriptor to set the appropriate output stream
-						// 1 for stdout, 2 for stderr
-						match redir.fd {
-							1 => { child.stdout(Stdio::from(file)); }
-							2 => { child.stderr(Stdio::from(file)); }
-							_ => eprintln!("{}: unsupported file descriptor {}", cmd, redir.fd),
-						}
-					}
-					
-					if let Err(e) = child.status() {
-						eprintln!("{cmd}: {e}");
-					}	
-				} else {
-					println!("{cmd}: not found");
+/// Parse commands separated by `;`, newlines, `&&` and `||`, up to the end of
+/// the tokens, a `)`, or one of the reserved `terminators` (e.g. `}`) where a
+/// command would start. The terminator itself is left for the caller.
+fn parse_list(tokens: &mut Tokens, terminators: &[&str]) -> Result<CommandList, ParseError> {
+	let mut commands = Vec::new();
+	let mut connector = Connector::Always;
+	loop {
+		// Blank lines can go between commands, and after `&&` or `||`
+		while tokens.next_if_eq(&Token::Newline).is_some() {}
+		match tokens.peek() {
+			None | Some(Token::RParen | Token::DSemi) if connector == Connector::Always => break,
+			Some(Token::Word(word)) if terminators.contains(&word.as_str()) => {
+				if connector != Connector::Always {
+					return Err(unexpected(tokens.peek()));
 				}
-			} 
+				break;
+			},
+			_ => {},
+		}
+
+		commands.push((connector, parse_pipeline(tokens)?));
+		connector = match tokens.peek() {
+			Some(Token::AndIf) => Connector::And,
+			Some(Token::OrIf) => Connector::Or,
+			Some(Token::Semi | Token::Newline) => Connector::Always,
+			Some(Token::Amp) => {
+				// `&` puts the whole `&&`/`||` chain before it in the background
+				let start = commands.iter().rposition(|(connector, _)| *connector == Connector::Always).unwrap_or(0);
+				let chain = commands.drain(start..).collect();
+				commands.push((Connector::Always, ShellCommand::Background(chain)));
+				Connector::Always
+			},
+			_ => break,
+		};
+		tokens.next();
+	}
+	Ok(commands)
+}
+
+/// Like `parse_list`, for the body of a construct, which can't be empty.
+fn parse_body(tokens: &mut Tokens, terminators: &[&str]) -> Result<CommandList, ParseError> {
+	let body = parse_list(tokens, terminators)?;
+	if body.is_empty() {
+		return Err(unexpected(tokens.peek()));
+	}
+	Ok(body)
+}
+
+/// Consume the reserved word `word`, which must come next.
+fn expect_word(tokens: &mut Tokens, word: &str) -> Result<(), ParseError> {
+	match tokens.next() {
+		Some(Token::Word(next)) if next == word => Ok(()),
+		token => Err(unexpected(token.as_ref())),
+	}
+}
+
+/// Parse a pipeline: commands joined by `|`, possibly negated with `!`. A
+/// single command is returned as it is.
+fn parse_pipeline(tokens: &mut Tokens) -> Result<ShellCommand, ParseError> {
+	if tokens.next_if(|token| matches!(token, Token::Word(word) if word == "!")).is_some() {
+		return Ok(ShellCommand::Not(Box::new(parse_pipeline(tokens)?)));
+	}
+	let mut stages = vec![parse_command(tokens)?];
+	while tokens.next_if_eq(&Token::Pipe).is_some() {
+		// The next command may start on a later line
+		while tokens.next_if_eq(&Token::Newline).is_some() {}
+		stages.push(parse_command(tokens)?);
+	}
+	Ok(match stages.len() {
+		1 => stages.remove(0),
+		_ => ShellCommand::Pipeline(stages),
+	})
+}
+
+/// Parse a single command: a compound command such as `if`, a function
+/// definition or a simple command.
+fn parse_command(tokens: &mut Tokens) -> Result<ShellCommand, ParseError> {
+	if let Some(command) = parse_compound(tokens)? {
+		return Ok(command);
+	}
+	if matches!(tokens.peek(), Some(Token::Word(word)) if CLOSING_WORDS.contains(&word.as_str())) {
+		return Err(unexpected(tokens.peek()));
+	}
+
+	let mut words = Vec::new();
+	while let Some(Token::Word(word)) = tokens.next_if(|token| matches!(token, Token::Word(_))) {
+		words.push(word);
+	}
+
+	if tokens.peek() == Some(&Token::LParen) {
+		// `name()` starts a function definition
+		let [name] = &mut words[..] else {
+			return Err(unexpected(tokens.peek()));
+		};
+		if name.contains(['\'', '"', '\\', '$', '`', '=', '/']) {
+			return Err(ParseError::Syntax(format!("`{name}': not a valid identifier")));
 		}
+		tokens.next();
+		if tokens.next_if_eq(&Token::RParen).is_none() {
+			return Err(unexpected(tokens.peek()));
+		}
+		return parse_function_body(std::mem::take(name), tokens);
+	}
+
+	if words.is_empty() {
+		return Err(unexpected(tokens.peek()));
+	}
+	new_token_parser(words)
+		.map(ShellCommand::Simple)
+		.map_err(|e| ParseError::Syntax(e.to_string()))
+}
+
+/// Parse a compound command, with any redirections after it, or return None
+/// when the tokens don't start one.
+fn parse_compound(tokens: &mut Tokens) -> Result<Option<ShellCommand>, ParseError> {
+	// Reserved words only count at the start of a command
+	let command = match tokens.peek() {
+		Some(Token::LParen) => {
+			tokens.next();
+			let body = parse_body(tokens, &[])?;
+			match tokens.next() {
+				Some(Token::RParen) => ShellCommand::Subshell(body),
+				token => return Err(unexpected(token.as_ref())),
+			}
+		},
+		Some(Token::Word(word)) if word == "{" => {
+			tokens.next();
+			let body = parse_body(tokens, &["}"])?;
+			expect_word(tokens, "}")?;
+			ShellCommand::Group(body)
+		},
+		Some(Token::Word(word)) if word == "[[" => {
+			tokens.next();
+			parse_conditional(tokens)?
+		},
+		Some(Token::Word(word)) if word == "if" => {
+			tokens.next();
+			parse_if(tokens)?
+		},
+		Some(Token::Word(word)) if word == "for" => {
+			tokens.next();
+			parse_for(tokens)?
+		},
+		Some(Token::Word(word)) if word == "while" || word == "until" => {
+			let until = word == "until";
+			tokens.next();
+			parse_while(tokens, until)?
+		},
+		Some(Token::Word(word)) if word == "case" => {
+			tokens.next();
+			parse_case(tokens)?
+		},
+		_ => return Ok(None),
+	};
+
+	// Redirections after the end apply to everything inside
+	let mut words = Vec::new();
+	while let Some(Token::Word(word)) = tokens.next_if(|token| matches!(token, Token::Word(_))) {
+		words.push(word);
+	}
+	if words.is_empty() {
+		return Ok(Some(command));
+	}
+	let parsed = new_token_parser(words).map_err(|e| ParseError::Syntax(e.to_string()))?;
+	let stray = match (parsed.assignments.first(), parsed.argv.first()) {
+		(Some((name, value)), _) => Some(format!("{name}={value}")),
+		(None, word) => word.cloned(),
+	};
+	if let Some(word) = stray {
+		return Err(unexpected(Some(&Token::Word(word))));
+	}
+	Ok(Some(ShellCommand::Redirected(Box::new(command), parsed.redirects)))
+}
+
+/// Parse the body following `name()`: usually `{ ...; }`, but any compound
+/// command will do.
+fn parse_function_body(name: String, tokens: &mut Tokens) -> Result<ShellCommand, ParseError> {
+	while tokens.next_if_eq(&Token::Newline).is_some() {}
+	match parse_compound(tokens)? {
+		Some(body) => Ok(ShellCommand::FunctionDef(name, vec![(Connector::Always, body)])),
+		None => Err(unexpected(tokens.peek())),
+	}
+}
+
+/// Parse the rest of `for name [in words...]; do body; done`.
+fn parse_for(tokens: &mut Tokens) -> Result<ShellCommand, ParseError> {
+	let name = match tokens.next() {
+		Some(Token::Word(name)) if vars::is_valid_name(&name) => name,
+		Some(Token::Word(name)) => return Err(ParseError::Syntax(format!("`{name}': not a valid identifier"))),
+		token => return Err(unexpected(token.as_ref())),
+	};
+
+	while tokens.next_if_eq(&Token::Newline).is_some() {}
+	let words = match tokens.peek() {
+		Some(Token::Word(word)) if word == "in" => {
+			tokens.next();
+			let mut words = Vec::new();
+			while let Some(Token::Word(word)) = tokens.next_if(|token| matches!(token, Token::Word(_))) {
+				words.push(word);
+			}
+			Some(words)
+		},
+		_ => None,
+	};
+	// The word list ends at a `;` or newline; `do` may also follow a bare name directly
+	if tokens.next_if(|token| matches!(token, Token::Semi | Token::Newline)).is_none() && words.is_some() {
+		return Err(unexpected(tokens.peek()));
+	}
+	while tokens.next_if_eq(&Token::Newline).is_some() {}
+
+	expect_word(tokens, "do")?;
+	let body = parse_body(tokens, &["done"])?;
+	expect_word(tokens, "done")?;
+	Ok(ShellCommand::For { name, words, body })
+}
+
+/// Parse the rest of `[[ expression ]]`.
+fn parse_conditional(tokens: &mut Tokens) -> Result<ShellCommand, ParseError> {
+	let mut words = Vec::new();
+	loop {
+		// Operators the tokenizer splits off are just more words of the expression
+		let word = match tokens.next() {
+			Some(Token::Word(word)) if word == "]]" => break,
+			Some(Token::Word(word)) => word,
+			Some(Token::AndIf) => "&&".to_owned(),
+			Some(Token::OrIf) => "||".to_owned(),
+			Some(Token::LParen) => "(".to_owned(),
+			Some(Token::RParen) => ")".to_owned(),
+			Some(Token::Pipe) => "|".to_owned(),
+			token => return Err(unexpected(token.as_ref())),
+		};
+		words.push(word);
+	}
+	condition::parse_conditional(&words)
+		.map(ShellCommand::Conditional)
+		.map_err(ParseError::Syntax)
+}
+
+/// Parse the rest of `while cond; do body; done`, or of an `until` loop.
+fn parse_while(tokens: &mut Tokens, until: bool) -> Result<ShellCommand, ParseError> {
+	let condition = parse_body(tokens, &["do"])?;
+	expect_word(tokens, "do")?;
+	let body = parse_body(tokens, &["done"])?;
+	expect_word(tokens, "done")?;
+	Ok(ShellCommand::While { until, condition, body })
+}
+
+/// Parse the rest of `case word in pattern | pattern) body ;; ... esac`.
+/// Patterns may start with `(`, and the last clause can leave out `;;`.
+fn parse_case(tokens: &mut Tokens) -> Result<ShellCommand, ParseError> {
+	let word = match tokens.next() {
+		Some(Token::Word(word)) => word,
+		token => return Err(unexpected(token.as_ref())),
+	};
+	while tokens.next_if_eq(&Token::Newline).is_some() {}
+	expect_word(tokens, "in")?;
+
+	let mut clauses = Vec::new();
+	loop {
+		while tokens.next_if_eq(&Token::Newline).is_some() {}
+		if tokens.next_if(|token| matches!(token, Token::Word(word) if word == "esac")).is_some() {
+			break;
+		}
+
+		tokens.next_if_eq(&Token::LParen);
+		let mut patterns = Vec::new();
+		loop {
+			match tokens.next() {
+				Some(Token::Word(pattern)) => patterns.push(pattern),
+				token => return Err(unexpected(token.as_ref())),
+			}
+			match tokens.next() {
+				Some(Token::Pipe) => continue,
+				Some(Token::RParen) => break,
+				token => return Err(unexpected(token.as_ref())),
+			}
+		}
+		// A clause's body may be empty
+		clauses.push((patterns, parse_list(tokens, &["esac"])?));
+
+		if tokens.next_if_eq(&Token::DSemi).is_none() {
+			expect_word(tokens, "esac")?;
+			break;
+		}
+	}
+	Ok(ShellCommand::Case { word, clauses })
+}
+
+/// Parse the rest of `if cond; then body; [elif cond; then body;]... [else body;] fi`.
+fn parse_if(tokens: &mut Tokens) -> Result<ShellCommand, ParseError> {
+	let mut branches = Vec::new();
+	loop {
+		let condition = parse_body(tokens, &["then"])?;
+		expect_word(tokens, "then")?;
+		let body = parse_body(tokens, &["elif", "else", "fi"])?;
+		branches.push((condition, body));
+
+		match tokens.next() {
+			Some(Token::Word(word)) if word == "elif" => continue,
+			Some(Token::Word(word)) if word == "else" => {
+				let otherwise = parse_body(tokens, &["fi"])?;
+				expect_word(tokens, "fi")?;
+				return Ok(ShellCommand::If { branches, otherwise: Some(otherwise) });
+			},
+			Some(Token::Word(word)) if word == "fi" => return Ok(ShellCommand::If { branches, otherwise: None }),
+			token => return Err(unexpected(token.as_ref())),
+		}
+	}
+}
+
+fn open_redir(redir: &Redirection, opened: &HashMap<u8, File>) -> std::io::Result<fs::File> {
+    
+    match redir.mode {
+        RedirectMode::Truncate => File::create(&redir.path),
+        RedirectMode::Append   => OpenOptions::new()
+                                       .create(true)
+                                       .append(true)
+                                       .open(&redir.path),
+        RedirectMode::Read     => File::open(&redir.path),
+        RedirectMode::HereDoc { ref body, .. } => heredoc_file(body),
+        RedirectMode::HereString(ref text) => heredoc_file(&format!("{text}\n")),
+        RedirectMode::DupFd(source) => {
+            // Duplicate whatever the source fd currently points at, which is
+            // either an earlier redirection or the shell's own stream
+            if let Some(file) = opened.get(&source) {
+                return file.try_clone();
+            }
+            sys::dup_fd(source.into())
+                .map(File::from)
+                .map_err(|_| io::Error::other(format!("{source}: Bad file descriptor")))
+        }
+    }
+}
+
+/// Store a here-document body in an unlinked temporary file and return
+/// a handle positioned at its start, ready to be used as stdin.
+fn heredoc_file(body: &str) -> std::io::Result<fs::File> {
+	static COUNTER: AtomicUsize = AtomicUsize::new(0);
+	let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+	let path = env::temp_dir().join(format!("myshell-heredoc-{}-{n}", std::process::id()));
+
+	let mut file = OpenOptions::new().write(true).create_new(true).open(&path)?;
+	file.write_all(body.as_bytes())?;
+	let reader = File::open(&path);
+	fs::remove_file(&path)?; // The open handle keeps the data alive
+	reader
+}
+
+/// Open every redirection in order, producing the file each fd ends up pointing at.
+/// Order matters: `> out 2>&1` sends both to `out`, while `2>&1 > out` does not.
+fn open_redirects(redirects: &[Redirection]) -> std::io::Result<HashMap<u8, File>> {
+	let mut opened = HashMap::new();
+	for redir in redirects {
+		let file = open_redir(redir, &opened)?;
+		opened.insert(redir.fd, file);
+	}
+	Ok(opened)
+}
+
+/// Return a boxed writer that is either the redirection file
+/// or Stdout when no redirection was requested.
+fn writer_for_fd(redirects: &HashMap<u8, File>, fd: u8) -> std::io::Result<Box<dyn std::io::Write + '_>> {
+    if let Some(file) = redirects.get(&fd) { // If there is a redirection for this fd
+    	Ok(Box::new(file))
+	} else {
+		match fd {
+			1 => Ok(Box::new(io::stdout())),
+			2 => Ok(Box::new(io::stderr())),
+			_ => Err(io::Error::other(
+				format!("unsupported fd {fd}"),
+			)),
+		}
+	}
+}
+
+/// Run `f` with the shell's own fds pointing where `redirects` say, as needed
+/// by commands that run inside the shell rather than in a child, such as
+/// function bodies. The original fds are put back afterwards.
+fn with_redirects<T>(redirects: &HashMap<u8, File>, f: impl FnOnce() -> T) -> io::Result<T> {
+	io::stdout().flush()?; // Output so far belongs to the old stdout
+	let mut saved = Vec::new();
+	for (&fd, file) in redirects {
+		saved.push((fd, sys::dup_fd(fd.into()).ok())); // None when the fd wasn't open
+		sys::dup_onto(file.as_raw_fd(), fd.into())?;
+	}
+
+	let result = f();
+
+	io::stdout().flush()?;
+	for (fd, original) in saved {
+		match original {
+			Some(original) => sys::dup_onto(original.as_raw_fd(), fd.into())?,
+			None => sys::close_fd(fd.into())?,
+		}
+	}
+	Ok(result)
+}
+
+/// The `$?` value for a finished process: its exit code, or 128 plus the
+/// number of the signal that killed or stopped it.
+fn exit_code(status: ExitStatus) -> i32 {
+	status
+		.code()
+		.or_else(|| status.signal().or(status.stopped_signal()).map(|signal| 128 + signal))
+		.unwrap_or(1)
+}
+
+/// Everything the interpreter remembers between commands
+#[derive(Clone)]
+struct ShellState {
+	vars: Variables,
+	last_status: i32, // Exit status of the most recently executed command, for `$?`
+	path_index: PathIndex, // External commands in the PATH directories, read as lookups need them
+	hash_hits: HashMap<String, usize>, // How often each indexed command was run, for `hash`
+	history: History, // Lines entered at the prompt
+	completions: HashMap<String, CompletionSpec>, // Argument completion registered with `complete`
+	editing_mode: EditingMode, // Key bindings for the line editor, from `set -o`
+	options: options::Options, // Turned on and off with `set`
+	shopt: options::Shopt, // Turned on and off with `shopt`
+	jobs: JobTable, // Stopped commands and those running in the background
+	last_background: Option<sys::Pid>, // The last command started with `&`, for `$!`
+	traps: HashMap<i32, String>, // Commands set with `trap` for each signal, or 0 for EXIT; empty to ignore it
+	interactive: bool, // Reading commands from the user at a prompt
+	job_control: bool, // Jobs get process groups of their own, and the foreground one the terminal
+	terminal_mode: Option<sys::Termios>, // The terminal's settings to give back after a job, under job control
+	dir_stack: Vec<PathBuf>, // Directories saved by `pushd`, below the working directory
+	disabled_builtins: HashSet<String>, // Turned off with `enable -n`
+	aliases: HashMap<String, String>, // Defined with `alias`, expanded as commands are parsed
+	functions: HashMap<String, Rc<CommandList>>, // Bodies of functions defined with `name() { ... }`
+	arg0: String, // `$0`, the shell or script name
+	positional: Vec<String>, // `$1` onwards, from the command line or a function call
+	loop_depth: usize, // Loops running in the current function or sourced file, for `break` and `continue`
+	call_depth: usize, // Functions and sourced files running, which `return` can leave
+	condition_depth: usize, // Conditions being run, whose failures don't trigger `set -e`
+	flow: Flow, // Set by `break`, `continue` and `return` for the command list to act on
+}
+
+impl ShellState {
+	fn new() -> Self {
+		let mut vars = Variables::from_env();
+		// Children see where the shell is, whatever PWD was inherited
+		if let Ok(cwd) = env::current_dir() {
+			vars.export("PWD", Some(&cwd.to_string_lossy()));
+		}
+
+		// History goes to HISTFILE, or ~/.myshell_history, capped at HISTSIZE entries
+		let history_file = match vars.get("HISTFILE") {
+			Some(file) => PathBuf::from(file),
+			None => Path::new(vars.get("HOME").unwrap_or("")).join(".myshell_history"),
+		};
+		let history_size = vars.get("HISTSIZE").and_then(|n| n.parse().ok()).unwrap_or(history::DEFAULT_SIZE);
+		let dirs = search_dirs(&vars);
+
+		ShellState {
+			history: History::load(history_file, history_size),
+			vars,
+			last_status: 0,
+			path_index: PathIndex::new(dirs),
+			hash_hits: HashMap::new(),
+			completions: HashMap::new(),
+			editing_mode: EditingMode::default(),
+			options: options::Options::default(),
+			shopt: options::Shopt::default(),
+			jobs: JobTable::default(),
+			last_background: None,
+			traps: HashMap::new(),
+			interactive: false,
+			job_control: false,
+			terminal_mode: None,
+			dir_stack: Vec::new(),
+			disabled_builtins: HashSet::new(),
+			aliases: HashMap::new(),
+			functions: HashMap::new(),
+			arg0: env::args().next().unwrap_or_else(|| "myshell".to_owned()),
+			positional: Vec::new(),
+			loop_depth: 0,
+			call_depth: 0,
+			condition_depth: 0,
+			flow: Flow::Normal,
+		}
+	}
+
+	/// Point the PATH index at the directories to search now, which forgets
+	/// how often commands were run if they have changed.
+	fn refresh_path_index(&mut self) {
+		if self.path_index.set_dirs(search_dirs(&self.vars)) {
+			self.hash_hits.clear();
+		}
+	}
+
+	/// Where the PATH command `name` is. Each directory is only read when a
+	/// lookup gets to it and again once it has changed, so commands installed
+	/// since are found too.
+	fn find_command(&mut self, name: &str) -> Option<PathBuf> {
+		// A name with a slash, like `./run.sh`, is a path to the file itself
+		if path_index::is_path(name) {
+			return Some(PathBuf::from(name)).filter(|path| path.is_file());
+		}
+		self.refresh_path_index();
+		self.path_index.find(name)
+	}
+
+	/// Forget the traps set with commands, as a subshell does, giving their
+	/// signals back their default actions. Ignored signals stay ignored.
+	fn reset_traps(&mut self) {
+		self.traps.retain(|&signal, action| {
+			if !action.is_empty() && signal != 0 {
+				sys::reset_signal(signal).ok();
+			}
+			action.is_empty()
+		});
+	}
+
+	/// Pass a hangup on to the jobs, except those marked with `disown -h`.
+	/// Stopped ones are continued so they can act on it.
+	fn hang_up_jobs(&self) {
+		for job in self.jobs.iter().filter(|job| !job.no_hup) {
+			self.signal_job(job.pid, sys::SIGHUP).ok();
+			if job.state == JobState::Stopped {
+				self.signal_job(job.pid, sys::SIGCONT).ok();
+			}
+		}
+	}
+
+	/// Send `signal_number` to the job led by `pid`, and so to its whole
+	/// process group, which commands a forked shell runs for it share.
+	fn signal_job(&self, pid: sys::Pid, signal_number: i32) -> io::Result<()> {
+		sys::signal_group(pid, signal_number)
+	}
+
+	/// In a forked copy of the shell that runs a job, put it in a process
+	/// group of its own and give back the default actions of the signals the
+	/// shell survives, so Ctrl-C and Ctrl-Z act on it. Commands it runs in
+	/// turn share its group. Without job control only a `background` job gets
+	/// a group, so it can still be signalled as a whole, and it reads
+	/// /dev/null rather than the terminal, as in bash.
+	fn start_job(&mut self, background: bool) -> io::Result<()> {
+		if std::mem::take(&mut self.job_control) {
+			sys::set_process_group(0, 0)?;
+			for signal in [sys::SIGINT, sys::SIGTSTP, sys::SIGTTOU, sys::SIGHUP] {
+				sys::reset_signal(signal)?;
+			}
+		} else if background {
+			sys::set_process_group(0, 0)?;
+			sys::dup_onto(File::open("/dev/null")?.as_raw_fd(), 0)?;
+		}
+		Ok(())
+	}
+
+	/// Hand the terminal to the job led by `pid`, which is put in a process
+	/// group of its own first in case it hasn't got there yet.
+	fn give_terminal(&self, pid: sys::Pid) {
+		if self.job_control {
+			sys::set_process_group(pid, pid).ok();
+			sys::set_foreground_group(0, pid).ok();
+		}
+	}
+
+	/// Take the terminal back from a foreground job that finished with
+	/// `status` or stopped. One that was stopped or killed may have left the
+	/// terminal in a mode of its own, which is undone; otherwise settings it
+	/// chose, e.g. with `stty`, are kept.
+	fn take_terminal(&mut self, status: ExitStatus) {
+		if !self.job_control {
+			return;
+		}
+		sys::set_foreground_group(0, sys::process_group()).ok();
+		if status.code().is_some() {
+			self.terminal_mode = sys::terminal_mode(0).ok();
+		} else if let Some(mode) = &self.terminal_mode {
+			sys::set_terminal_mode(0, mode).ok();
+		}
+	}
+
+	/// Whether `name` is a builtin that hasn't been turned off with `enable -n`.
+	fn is_builtin(&self, name: &str) -> bool {
+		BUILTIN_COMMANDS.contains(&name) && !self.disabled_builtins.contains(name)
+	}
+}
+
+/// The PATH directories that commands are looked for in. Those matching one
+/// of the glob patterns in MYSHELL_PATH_EXCLUDE, separated like the entries
+/// of PATH, are left out, e.g. `/mnt/*` to skip slow mounted drives.
+fn search_dirs(vars: &Variables) -> Vec<String> {
+	let exclude = vars.get("MYSHELL_PATH_EXCLUDE").unwrap_or_default();
+	let patterns: Vec<String> = env::split_paths(exclude)
+		.map(|pattern| pattern.to_string_lossy().into_owned())
+		.filter(|pattern| !pattern.is_empty())
+		.collect();
+	env::split_paths(vars.get("PATH").unwrap_or_default())
+		.map(|dir| dir.to_string_lossy().into_owned())
+		.filter(|dir| !patterns.iter().any(|pattern| expand::glob_match(pattern, dir)))
+		.collect()
+}
+
+// Define the built-in commands for this shell
+static BUILTIN_COMMANDS: [&str; 42] = ["type", "command", "builtin", "enable", "hash", "alias", "unalias", "eval", "exec", "kill", "wait", "trap", "jobs", "fg", "bg", "disown", "echo", "printf", "read", "true", "false", ":", "exit", "pwd", "cd", "pushd", "popd", "dirs", "export", "unset", "history", "complete", "set", "source", ".", "shift", "break", "continue", "return", "shopt", "test", "["];
+
+/// Something a command name can refer to.
+enum CommandKind {
+	Alias(String), // With its value
+	Function,
+	Builtin,
+	External(PathBuf), // An executable found on PATH
+}
+
+/// Everything `name` refers to, in the order the shell looks: aliases as
+/// commands are parsed, then functions, builtins and PATH as they run. With
+/// `all_files`, every file of that name in the PATH directories is included,
+/// rather than just the indexed one that would run.
+fn resolve_command(name: &str, state: &mut ShellState, all_files: bool) -> Vec<CommandKind> {
+	let mut found = Vec::new();
+	if let Some(value) = state.aliases.get(name) {
+		found.push(CommandKind::Alias(value.clone()));
+	}
+	if state.functions.contains_key(name) {
+		found.push(CommandKind::Function);
+	}
+	if state.is_builtin(name) {
+		found.push(CommandKind::Builtin);
+	}
+	if all_files {
+		state.refresh_path_index();
+		found.extend(state.path_index.find_all(name).into_iter().map(CommandKind::External));
+	} else if let Some(path) = state.find_command(name) {
+		found.push(CommandKind::External(path));
+	}
+	found
+}
+
+/// The word `type -t` prints for a kind of command.
+fn command_kind_word(kind: &CommandKind) -> &'static str {
+	match kind {
+		CommandKind::Alias(_) => "alias",
+		CommandKind::Function => "function",
+		CommandKind::Builtin => "builtin",
+		CommandKind::External(_) => "file",
+	}
+}
+
+/// Say what `name` refers to, the way `type` does.
+fn describe_command(name: &str, kind: &CommandKind) -> String {
+	match kind {
+		CommandKind::Alias(value) => format!("{name} is aliased to `{value}'"),
+		CommandKind::Function => format!("{name} is a function"),
+		CommandKind::Builtin => format!("{name} is a shell builtin"),
+		CommandKind::External(path) => format!("{name} is {}", path.display()),
+	}
+}
+
+/// Run a single parsed command and return its exit status.
+fn run_command(mut command: ParsedCommand, state: &mut ShellState) -> Result<i32, Box<dyn Error>> {
+	// Words are expanded right before running, so `$?` sees the latest status
+	SUBSTITUTION_STATUS.with(|last| last.set(None));
+	if let Err(e) = expand_command(&mut command, state) {
+		diagnostics::error(e);
+		return Ok(1);
+	}
+	let ParsedCommand { assignments, argv, redirects } = command;
+	if state.options.xtrace {
+		trace_command(&assignments, &argv, state);
+	}
+
+	// Open all redirection targets up front, like bash does, so files are
+	// created even when the command never writes to them
+	let redirects = match open_redirects(&redirects) {
+		Ok(r) => r,
+		Err(e) => {
+			diagnostics::error(e);
+			return Ok(1);
+		}
+	};
+
+	let mut argv = argv.iter().map(|x| x.as_str());
+	let Some(mut cmd) = argv.next() else {
+		// No command word: assignments set shell variables, and redirections
+		// alone, e.g. `> file`, just create the file
+		for (name, value) in &assignments {
+			state.vars.set(name, value);
+		}
+		return Ok(SUBSTITUTION_STATUS.with(|last| last.get()).unwrap_or(0));
+	};
+
+	// `command name args` runs the builtin or PATH command `name`, even when
+	// a function has the same name. With options like `-v` it is a builtin
+	// Likewise `builtin name args` runs the builtin `name`, and only that
+	let mut skip_functions = false;
+	loop {
+		match cmd {
+			"command" => {
+				let mut rest = argv.clone();
+				let name = match rest.next() {
+					Some("-p" | "--") => rest.next(),
+					name => name,
+				};
+				match name {
+					Some(name) if !name.starts_with('-') => {
+						cmd = name;
+						argv = rest;
+					},
+					_ => break,
+				}
+			},
+			"builtin" => match argv.next() {
+				Some(name) if state.is_builtin(name) => cmd = name,
+				Some(name) => {
+					diagnostics::error_to(redirects.get(&2), format_args!("builtin: {name}: not a shell builtin"))?;
+					return Ok(1);
+				},
+				None => return Ok(0),
+			},
+			_ => break,
+		}
+		skip_functions = true;
+	}
+
+	// Functions come before builtins and PATH, and run in this shell with
+	// their arguments as the positional parameters
+	if let Some(body) = state.functions.get(cmd).filter(|_| !skip_functions).cloned() {
+		let caller_args = std::mem::replace(&mut state.positional, argv.map(str::to_owned).collect());
+		let caller_loops = enter_call(state);
+		let flow = with_redirects(&redirects, || run_command_list((*body).clone(), state));
+		leave_call(state, caller_loops);
+		state.positional = caller_args;
+		flow??; // `return` stops here
+		return Ok(state.last_status);
+	}
+
+	// Validate input
+	let status = match cmd {
+		// Builtins turned off with `enable -n` leave the name to PATH
+		_ if state.disabled_builtins.contains(cmd) => run_external(cmd, argv, assignments, redirects, state)?,
+
+		"type" => {
+			// `-a` shows everything a name refers to, `-t` just the kind of each
+			let mut all = false;
+			let mut terse = false;
+			let mut args = argv.peekable();
+			while let Some(flags) = args.next_if(|arg| arg.starts_with('-') && arg.len() > 1) {
+				for flag in flags[1..].chars() {
+					match flag {
+						'a' => all = true,
+						't' => terse = true,
+						_ => {
+							diagnostics::error_to(redirects.get(&2), format_args!("type: -{flag}: invalid option"))?;
+							return Ok(2);
+						},
+					}
+				}
+			}
+			let queries: Vec<&str> = args.collect();
+			if queries.is_empty() {    // no argument after `type`
+				diagnostics::error_to(redirects.get(&2), format_args!("type: missing operand"))?;
+				return Ok(1);
+			}
+
+			let mut out = writer_for_fd(&redirects, 1)?;
+			let mut status = 0;
+			for query in queries {
+				let found = resolve_command(query, state, all);
+				if found.is_empty() {
+					// `-t` says nothing about names that aren't found
+					if !terse {
+						writeln!(out, "{query}: not found")?;
+					}
+					status = 1;
+				}
+				for kind in found.iter().take(if all { found.len() } else { 1 }) {
+					match terse {
+						true => writeln!(out, "{}", command_kind_word(kind))?,
+						false => writeln!(out, "{}", describe_command(query, kind))?,
+					}
+				}
+			}
+			status
+		}
+
+		"alias" => {
+			let mut status = 0;
+			let mut out = writer_for_fd(&redirects, 1)?;
+			let args: Vec<&str> = argv.skip_while(|arg| *arg == "-p").collect();
+			if args.is_empty() {
+				let mut aliases: Vec<_> = state.aliases.iter().collect();
+				aliases.sort();
+				for (name, value) in aliases {
+					writeln!(out, "alias {name}={}", expand::quote(value))?;
+				}
+			}
+			for arg in args {
+				match arg.split_once('=') {
+					Some((name, value)) if is_valid_alias_name(name) => {
+						state.aliases.insert(name.to_owned(), value.to_owned());
+					},
+					Some((name, _)) => {
+						diagnostics::error_to(redirects.get(&2), format_args!("alias: `{name}': invalid alias name"))?;
+						status = 1;
+					},
+					None => match state.aliases.get(arg) {
+						Some(value) => writeln!(out, "alias {arg}={}", expand::quote(value))?,
+						None => {
+							diagnostics::error_to(redirects.get(&2), format_args!("alias: {arg}: not found"))?;
+							status = 1;
+						},
+					},
+				}
+			}
+			status
+		},
+
+		"unalias" => {
+			let mut status = 0;
+			for name in argv {
+				if name == "-a" {
+					state.aliases.clear();
+				} else if state.aliases.remove(name).is_none() {
+					diagnostics::error_to(redirects.get(&2), format_args!("unalias: {name}: not found"))?;
+					status = 1;
+				}
+			}
+			status
+		},
+
+		"echo" => {
+			// Leading words made up of `-` and the letters n, e and E are options
+			let args: Vec<&str> = argv.collect();
+			let is_option = |arg: &str| arg.len() > 1 && arg.starts_with('-') && arg[1..].chars().all(|c| matches!(c, 'n' | 'e' | 'E'));
+			let option_count = args.iter().take_while(|arg| is_option(arg)).count();
+			let mut newline = true;
+			let mut escapes = false;
+			for flag in args[..option_count].iter().flat_map(|arg| arg[1..].chars()) {
+				match flag {
+					'n' => newline = false,
+					'e' => escapes = true,
+					_ => escapes = false,
+				}
+			}
+
+			let mut text = args[option_count..].join(" ");
+			if escapes {
+				let (decoded, stopped) = expand::backslash_escapes(&text, true);
+				text = decoded;
+				newline &= !stopped;
+			}
+			let mut out = writer_for_fd(&redirects, 1)?;
+			write!(out, "{text}")?;
+			if newline {
+				writeln!(out)?;
+			}
+			0
+		},
+
+		"exit" => {
+			let args: Vec<&str> = argv.collect();
+			// Like bash, a bad status still exits, but too many arguments don't
+			let status = match args.as_slice() {
+				[] => state.last_status,
+				[arg] => match arg.trim().parse::<i64>() {
+					Ok(n) => n.rem_euclid(256) as i32,
+					Err(_) => {
+						diagnostics::error_to(redirects.get(&2), format_args!("exit: {arg}: numeric argument required"))?;
+						2
+					},
+				},
+				_ => {
+					diagnostics::error_to(redirects.get(&2), "exit: too many arguments")?;
+					return Ok(1);
+				},
+			};
+			exit_shell(state, status);
+		},
+
+		"pwd" => {
+			match env::current_dir() {
+				Ok(path) => {
+					let mut out = writer_for_fd(&redirects, 1)?;
+					writeln!(out, "{}", path.display())?;
+					0
+				}
+				Err(e) => {
+					diagnostics::error_to(redirects.get(&2), format_args!("pwd: {e}"))?;
+					1
+				}
+			}
+		},
+
+		"export" => {
+			let mut status = 0;
+			let names: Vec<&str> = argv.collect();
+
+			if names.is_empty() || names == ["-p"] {
+				let mut out = writer_for_fd(&redirects, 1)?;
+				for (name, value) in state.vars.exported() {
+					match value {
+						Some(value) => writeln!(out, "declare -x {name}=\"{value}\"")?,
+						None => writeln!(out, "declare -x {name}")?,
+					}
+				}
+			}
+
+			for arg in names.into_iter().filter(|&a| a != "-p") {
+				let (name, value) = match arg.split_once('=') {
+					Some((name, value)) => (name, Some(value)),
+					None => (arg, None),
+				};
+				if vars::is_valid_name(name) {
+					state.vars.export(name, value);
+				} else {
+					diagnostics::error_to(redirects.get(&2), format_args!("export: `{arg}': not a valid identifier"))?;
+					status = 1;
+				}
+			}
+			status
+		},
+
+		"unset" => {
+			let mut status = 0;
+			let mut functions = None; // -f: names refer to functions, -v: to variables
+
+			for arg in argv {
+				match arg {
+					"-v" => functions = Some(false),
+					"-f" => functions = Some(true),
+					_ if functions == Some(true) => {
+						state.functions.remove(arg);
+					},
+					// Without an option, a name that isn't a variable may be a function
+					_ if functions.is_none() && state.vars.get(arg).is_none() && state.functions.remove(arg).is_some() => {},
+					_ if vars::is_valid_name(arg) => state.vars.unset(arg),
+					_ => {
+						diagnostics::error_to(redirects.get(&2), format_args!("unset: `{arg}': not a valid identifier"))?;
+						status = 1;
+					}
+				}
+			}
+			status
+		},
+
+		"history" => {
+			let entries = state.history.entries();
+			match argv.next() {
+				Some("-c") => {
+					state.history.clear();
+					0
+				},
+				arg => {
+					// `history N` lists only the last N entries
+					let count = match arg.map(str::parse::<usize>) {
+						None => entries.len(),
+						Some(Ok(n)) => n.min(entries.len()),
+						Some(Err(_)) => {
+							diagnostics::error_to(redirects.get(&2), format_args!("history: {}: numeric argument required", arg.unwrap()))?;
+							return Ok(1);
+						},
+					};
+					let mut out = writer_for_fd(&redirects, 1)?;
+					let first = entries.len() - count;
+					for (i, entry) in entries.iter().enumerate().skip(first) {
+						writeln!(out, "{:5}  {entry}", i + 1)?;
+					}
+					0
+				},
+			}
+		},
+
+		"complete" => {
+			let mut spec = None;
+			let mut remove = false;
+			let mut names = Vec::new();
+
+			while let Some(arg) = argv.next() {
+				match arg {
+					"-W" | "-C" => {
+						let Some(value) = argv.next() else {
+							diagnostics::error_to(redirects.get(&2), format_args!("complete: {arg}: option requires an argument"))?;
+							return Ok(2);
+						};
+						spec = Some(if arg == "-W" {
+							CompletionSpec::WordList(value.to_owned())
+						} else {
+							CompletionSpec::Command(value.to_owned())
+						});
+					},
+					"-r" => remove = true,
+					"-p" => {},
+					_ => names.push(arg),
+				}
+			}
+
+			match spec {
+				Some(spec) if !remove => {
+					for name in names {
+						state.completions.insert(name.to_owned(), spec.clone());
+					}
+				},
+				_ if remove && names.is_empty() => state.completions.clear(),
+				_ if remove => {
+					for name in names {
+						state.completions.remove(name);
+					}
+				},
+				_ => {
+					// With no spec, print the ones registered, in a reusable form
+					let mut out = writer_for_fd(&redirects, 1)?;
+					let mut registered: Vec<_> = state.completions.iter().collect();
+					registered.sort_by_key(|(name, _)| name.as_str());
+					for (name, spec) in registered {
+						if names.is_empty() || names.contains(&name.as_str()) {
+							writeln!(out, "{}", spec.describe(name))?;
+						}
+					}
+				},
+			}
+			0
+		},
+
+		"set" => {
+			let mut status = 0;
+			while let Some(arg) = argv.next() {
+				let on = arg.starts_with('-'); // `-` turns options on and `+` off
+				match arg {
+					"--" => {
+						state.positional = argv.map(str::to_owned).collect();
+						break;
+					},
+					"-o" | "+o" => {
+						let Some(option) = argv.next() else {
+							// Bare `set -o` lists the options and their state
+							let mut out = writer_for_fd(&redirects, 1)?;
+							let mut options = state.options.list();
+							options.push(("emacs", state.editing_mode == EditingMode::Emacs));
+							options.push(("vi", state.editing_mode == EditingMode::Vi));
+							options.sort();
+							for (name, on) in options {
+								writeln!(out, "{name:<15}\t{}", if on { "on" } else { "off" })?;
+							}
+							break;
+						};
+						if !set_named_option(state, option, on) {
+							diagnostics::error_to(redirects.get(&2), format_args!("set: {option}: invalid option name"))?;
+							status = 1;
+						}
+					},
+					_ if arg.len() > 1 && (arg.starts_with('-') || arg.starts_with('+')) => {
+						// Flags can be combined, as in `set -eu`, and `o` takes the
+						// next word as an option name, as in `set -euo pipefail`
+						for flag in arg[1..].chars() {
+							if flag == 'o' {
+								let option = argv.next().unwrap_or_default();
+								if !set_named_option(state, option, on) {
+									diagnostics::error_to(redirects.get(&2), format_args!("set: {option}: invalid option name"))?;
+									status = 1;
+								}
+								continue;
+							}
+							let Some(name) = options::Options::name_of_flag(flag) else {
+								diagnostics::error_to(redirects.get(&2), format_args!("set: {}{flag}: invalid option", &arg[..1]))?;
+								return Ok(2);
+							};
+							state.options.set(name, on);
+						}
+					},
+					_ => {
+						// The first word that isn't an option starts the new positional parameters
+						state.positional = std::iter::once(arg).chain(argv).map(str::to_owned).collect();
+						break;
+					},
+				}
+			}
+			status
+		},
+
+		"true" | ":" => 0,
+		"false" => 1,
+
+		"printf" => {
+			let mut args: Vec<&str> = argv.collect();
+			if args.first() == Some(&"--") {
+				args.remove(0);
+			}
+			let Some((format, args)) = args.split_first() else {
+				diagnostics::error_to(redirects.get(&2), "printf: usage: printf format [arguments]")?;
+				return Ok(2);
+			};
+			let formatted = printf::format(format, args);
+			write!(writer_for_fd(&redirects, 1)?, "{}", formatted.text)?;
+			for e in &formatted.errors {
+				diagnostics::error_to(redirects.get(&2), format_args!("printf: {e}"))?;
+			}
+			if formatted.errors.is_empty() { 0 } else { 1 }
+		},
+
+		"read" => {
+			let mut raw = false;
+			let mut prompt = None;
+			let mut args = argv.peekable();
+			while let Some(arg) = args.next_if(|arg| arg.starts_with('-') && arg.len() > 1) {
+				if arg == "--" {
+					break;
+				}
+				for (i, flag) in arg.char_indices().skip(1) {
+					match flag {
+						'r' => raw = true,
+						'p' => {
+							// The prompt is the rest of the word, or the next one
+							let rest = &arg[i + 1..];
+							prompt = match rest.is_empty() { false => Some(rest), true => args.next() };
+							if prompt.is_none() {
+								diagnostics::error_to(redirects.get(&2), "read: -p: option requires an argument")?;
+								return Ok(2);
+							}
+							break;
+						},
+						_ => {
+							diagnostics::error_to(redirects.get(&2), format_args!("read: -{flag}: invalid option"))?;
+							return Ok(2);
+						},
+					}
+				}
+			}
+			let mut names: Vec<&str> = args.collect();
+			if let Some(name) = names.iter().find(|name| !vars::is_valid_name(name)) {
+				diagnostics::error_to(redirects.get(&2), format_args!("read: `{name}': not a valid identifier"))?;
+				return Ok(1);
+			}
+
+			let fd = redirects.get(&0).map_or(0, |file| file.as_raw_fd());
+			if let Some(prompt) = prompt.filter(|_| sys::is_terminal(fd)) {
+				let mut err = writer_for_fd(&redirects, 2)?;
+				write!(err, "{prompt}")?;
+				err.flush()?;
+			}
+			let (line, complete) = match read::read_line(fd, raw) {
+				Ok(read) => read,
+				Err(e) => {
+					diagnostics::error_to(redirects.get(&2), format_args!("read: {e}"))?;
+					return Ok(1);
+				},
+			};
+			// Without names, the whole line goes to REPLY unsplit. As in
+			// `IFS=: read a b`, an IFS given just for this command wins
+			let ifs = match names.is_empty() {
+				true => "",
+				false => assignments
+					.iter()
+					.rfind(|(name, _)| name == "IFS")
+					.map(|(_, value)| value.as_str())
+					.or_else(|| state.vars.get("IFS"))
+					.unwrap_or(" \t\n"),
+			};
+			if names.is_empty() {
+				names.push("REPLY");
+			}
+			let values = read::split_fields(&line, names.len(), ifs, raw);
+			for (name, value) in names.iter().zip(values) {
+				state.vars.set(name, &value);
+			}
+			// At end of input, whatever came before it is still assigned
+			if complete { 0 } else { 1 }
+		},
+
+		"test" | "[" => {
+			let mut args: Vec<&str> = argv.collect();
+			if cmd == "[" && args.pop() != Some("]") {
+				diagnostics::error_to(redirects.get(&2), "[: missing `]'")?;
+				return Ok(2);
+			}
+			match condition::test(&args) {
+				Ok(true) => 0,
+				Ok(false) => 1,
+				Err(e) => {
+					diagnostics::error_to(redirects.get(&2), format_args!("{cmd}: {e}"))?;
+					2
+				},
+			}
+		},
+
+		"shopt" => {
+			let mut setting = None; // Some(true) for `-s`, Some(false) for `-u`
+			let mut print = false;
+			let mut quiet = false;
+			let mut names = Vec::new();
+			for arg in argv {
+				match arg {
+					"-s" => setting = Some(true),
+					"-u" => setting = Some(false),
+					"-p" => print = true,
+					"-q" => quiet = true,
+					_ if arg.starts_with('-') => {
+						diagnostics::error_to(redirects.get(&2), format_args!("shopt: {arg}: invalid option"))?;
+						return Ok(2);
+					},
+					_ => names.push(arg),
+				}
+			}
+
+			let mut status = 0;
+			if let (Some(on), false) = (setting, names.is_empty()) {
+				for name in names {
+					if !state.shopt.set(name, on) {
+						diagnostics::error_to(redirects.get(&2), format_args!("shopt: {name}: invalid shell option name"))?;
+						status = 1;
+					}
+				}
+				return Ok(status);
+			}
+
+			// Otherwise show the named options, or every option (only those
+			// on for `-s`, or off for `-u`). Asking about an option that is
+			// off fails, so `shopt -q name` tests it
+			let mut listed = Vec::new();
+			if names.is_empty() {
+				listed = state.shopt.list();
+				listed.retain(|&(_, on)| setting.map_or(true, |wanted| wanted == on));
+			} else {
+				for name in names {
+					match state.shopt.get(name) {
+						Some(on) => {
+							listed.push((name, on));
+							if !on {
+								status = 1;
+							}
+						},
+						None => {
+							diagnostics::error_to(redirects.get(&2), format_args!("shopt: {name}: invalid shell option name"))?;
+							status = 1;
+						},
+					}
+				}
+			}
+			if !quiet {
+				let mut out = writer_for_fd(&redirects, 1)?;
+				for (name, on) in listed {
+					if print {
+						writeln!(out, "shopt {} {name}", if on { "-s" } else { "-u" })?;
+					} else {
+						writeln!(out, "{name:<15}\t{}", if on { "on" } else { "off" })?;
+					}
+				}
+			}
+			status
+		},
+
+		"cd" => {
+			// If no argument is given, change to the home directory,
+			// or to the root directory if HOME is not set
+			let (query, mut announce) = match argv.next() {
+				// `cd -` goes back to the previous directory and says where that is
+				Some("-") => match state.vars.get("OLDPWD") {
+					Some(old) => (old.to_owned(), true),
+					None => {
+						diagnostics::error_to(redirects.get(&2), "cd: OLDPWD not set")?;
+						return Ok(1);
+					},
+				},
+				Some(q) => (q.to_owned(), false),
+				None => (state.vars.get("HOME").unwrap_or("/").to_owned(), false),
+			};
+
+			let mut dir = Path::new(&query).canonicalize();
+			// A relative name missing here is looked for in the CDPATH
+			// directories, unless it is explicitly relative like `./src`
+			let explicit = matches!(Path::new(&query).components().next(), Some(Component::RootDir | Component::CurDir | Component::ParentDir));
+			if dir.is_err() && !explicit {
+				let cdpath = state.vars.get("CDPATH").unwrap_or_default();
+				let found = env::split_paths(cdpath)
+					.filter(|entry| !entry.as_os_str().is_empty())
+					.filter_map(|entry| entry.join(&query).canonicalize().ok())
+					.find(|path| path.is_dir());
+				if let Some(path) = found {
+					dir = Ok(path);
+					announce = true;
+				}
+			}
+			match dir {
+				Err(_) => {
+					diagnostics::error_to(redirects.get(&2), format_args!("cd: {query}: No such file or directory"))?;
+					1
+				},
+				Ok(path) => {
+					if let Err(e) = change_dir(&path, state) {
+						diagnostics::error_to(redirects.get(&2), format_args!("cd: {query}: {e}"))?;
+						return Ok(1);
+					}
+					if announce {
+						writeln!(writer_for_fd(&redirects, 1)?, "{}", path.display())?;
+					}
+					0
+				}
+			}
+		},
+
+		"pushd" => {
+			let stack = dir_stack(state);
+			let new_stack: Vec<PathBuf> = match argv.next() {
+				// On its own, `pushd` swaps the top two directories
+				None if stack.len() < 2 => {
+					diagnostics::error_to(redirects.get(&2), "pushd: no other directory")?;
+					return Ok(1);
+				},
+				None => {
+					let mut stack = stack;
+					stack.swap(0, 1);
+					stack
+				},
+				Some(arg) => match stack_index(arg, stack.len()) {
+					// `+N` and `-N` rotate the stack to bring that entry to the top
+					Some(Ok(n)) => {
+						let mut stack = stack;
+						stack.rotate_left(n);
+						stack
+					},
+					Some(Err(e)) => {
+						diagnostics::error_to(redirects.get(&2), format_args!("pushd: {e}"))?;
+						return Ok(1);
+					},
+					None => match Path::new(arg).canonicalize() {
+						Ok(path) => std::iter::once(path).chain(stack).collect(),
+						Err(_) => {
+							diagnostics::error_to(redirects.get(&2), format_args!("pushd: {arg}: No such file or directory"))?;
+							return Ok(1);
+						},
+					},
+				},
+			};
+			if let Err(e) = change_dir(&new_stack[0], state) {
+				diagnostics::error_to(redirects.get(&2), format_args!("pushd: {}: {e}", new_stack[0].display()))?;
+				return Ok(1);
+			}
+			state.dir_stack = new_stack[1..].to_vec();
+			print_dir_stack(state, &mut writer_for_fd(&redirects, 1)?, DirsFormat::Line, false)?;
+			0
+		},
+
+		"popd" => {
+			let mut stack = dir_stack(state);
+			if stack.len() < 2 {
+				diagnostics::error_to(redirects.get(&2), "popd: directory stack empty")?;
+				return Ok(1);
+			}
+			let index = match argv.next() {
+				None => 0,
+				Some(arg) => match stack_index(arg, stack.len()) {
+					Some(Ok(n)) => n,
+					Some(Err(e)) => {
+						diagnostics::error_to(redirects.get(&2), format_args!("popd: {e}"))?;
+						return Ok(1);
+					},
+					None => {
+						diagnostics::error_to(redirects.get(&2), format_args!("popd: {arg}: invalid argument"))?;
+						return Ok(2);
+					},
+				},
+			};
+			stack.remove(index);
+			// Removing the top moves to the directory below it
+			if index == 0 {
+				if let Err(e) = change_dir(&stack[0], state) {
+					diagnostics::error_to(redirects.get(&2), format_args!("popd: {}: {e}", stack[0].display()))?;
+					return Ok(1);
+				}
+			}
+			state.dir_stack = stack.split_off(1);
+			print_dir_stack(state, &mut writer_for_fd(&redirects, 1)?, DirsFormat::Line, false)?;
+			0
+		},
+
+		"dirs" => {
+			let mut format = DirsFormat::Line;
+			let mut long = false;
+			let mut clear = false;
+			for arg in argv {
+				let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+					diagnostics::error_to(redirects.get(&2), format_args!("dirs: {arg}: invalid argument"))?;
+					return Ok(2);
+				};
+				for flag in flags.chars() {
+					match flag {
+						'c' => clear = true,
+						'l' => long = true,
+						'p' => format = DirsFormat::PerLine,
+						'v' => format = DirsFormat::Numbered,
+						_ => {
+							diagnostics::error_to(redirects.get(&2), format_args!("dirs: -{flag}: invalid option"))?;
+							return Ok(2);
+						},
+					}
+				}
+			}
+			// Clearing the stack prints nothing
+			if clear {
+				state.dir_stack.clear();
+			} else {
+				print_dir_stack(state, &mut writer_for_fd(&redirects, 1)?, format, long)?;
+			}
+			0
+		},
+
+		"shift" => {
+			let count = match argv.next().map(str::parse::<usize>) {
+				None => 1,
+				Some(Ok(n)) => n,
+				Some(Err(_)) => {
+					diagnostics::error_to(redirects.get(&2), "shift: numeric argument required")?;
+					return Ok(1);
+				},
+			};
+			if count > state.positional.len() {
+				diagnostics::error_to(redirects.get(&2), format_args!("shift: {count}: shift count out of range"))?;
+				1
+			} else {
+				state.positional.drain(..count);
+				0
+			}
+		},
+
+		"source" | "." => {
+			let Some(name) = argv.next() else {
+				diagnostics::error_to(redirects.get(&2), format_args!("{cmd}: filename argument required"))?;
+				return Ok(2);
+			};
+			// Like bash, a bare name is looked for in PATH before the working directory
+			let path = if path_index::is_path(name) {
+				PathBuf::from(name)
+			} else {
+				env::split_paths(state.vars.get("PATH").unwrap_or_default())
+					.map(|dir| dir.join(name))
+					.find(|path| path.is_file())
+					.unwrap_or_else(|| PathBuf::from(name))
+			};
+
+			match fs::read_to_string(&path) {
+				// The file runs in this shell, so its assignments and `cd`s stay in effect
+				Ok(source) => {
+					let caller_loops = enter_call(state);
+					let status = run_source(&source, state);
+					leave_call(state, caller_loops);
+					status.unwrap_or_else(|e| {
+						diagnostics::error(e);
+						2
+					})
+				},
+				Err(e) => {
+					diagnostics::error_to(redirects.get(&2), format_args!("{cmd}: {name}: {e}"))?;
+					1
+				},
+			}
+		},
+
+		"command" => {
+			// Only options are left here: `command -v name` says how `name` would
+			// run, as something the shell can read back, and `-V` describes it
+			let args: Vec<&str> = argv.collect();
+			let (verbose, names) = match args.split_first() {
+				None => return Ok(0),
+				Some((&"-v", names)) => (false, names),
+				Some((&"-V", names)) => (true, names),
+				Some((option, _)) => {
+					diagnostics::error_to(redirects.get(&2), format_args!("command: {option}: invalid option"))?;
+					return Ok(2);
+				},
+			};
+			let mut out = writer_for_fd(&redirects, 1)?;
+			let mut status = 0;
+			for name in names {
+				match resolve_command(name, state, false).first() {
+					Some(kind) if verbose => writeln!(out, "{}", describe_command(name, kind))?,
+					Some(CommandKind::Alias(value)) => writeln!(out, "alias {name}={}", expand::quote(value))?,
+					Some(CommandKind::External(path)) => writeln!(out, "{}", path.display())?,
+					Some(_) => writeln!(out, "{name}")?,
+					None => {
+						if verbose {
+							diagnostics::error_to(redirects.get(&2), format_args!("command: {name}: not found"))?;
+						}
+						status = 1;
+					},
+				}
+			}
+			status
+		},
+
+		"enable" => {
+			let mut disable = false;
+			let mut all = false;
+			let mut names = Vec::new();
+			for arg in argv {
+				match arg {
+					"-n" => disable = true,
+					"-a" => all = true,
+					"-p" => {},
+					_ if arg.starts_with('-') => {
+						diagnostics::error_to(redirects.get(&2), format_args!("enable: {arg}: invalid option"))?;
+						return Ok(2);
+					},
+					_ => names.push(arg),
+				}
+			}
+
+			// Without names, list the builtins: the enabled ones, those turned
+			// off with `-n`, or all of them with `-a`
+			if names.is_empty() {
+				let mut out = writer_for_fd(&redirects, 1)?;
+				let mut builtins = BUILTIN_COMMANDS.to_vec();
+				builtins.sort();
+				for name in builtins {
+					let off = state.disabled_builtins.contains(name);
+					if all || off == disable {
+						writeln!(out, "enable {}{name}", if off { "-n " } else { "" })?;
+					}
+				}
+				return Ok(0);
+			}
+			let mut status = 0;
+			for name in names {
+				if !BUILTIN_COMMANDS.contains(&name) {
+					diagnostics::error_to(redirects.get(&2), format_args!("enable: {name}: not a shell builtin"))?;
+					status = 1;
+				} else if disable {
+					state.disabled_builtins.insert(name.to_owned());
+				} else {
+					state.disabled_builtins.remove(name);
+				}
+			}
+			status
+		},
+
+		"hash" => {
+			let mut reset = false;
+			let mut names = Vec::new();
+			for arg in argv {
+				match arg {
+					"-r" => reset = true,
+					_ if arg.starts_with('-') => {
+						diagnostics::error_to(redirects.get(&2), format_args!("hash: {arg}: invalid option"))?;
+						return Ok(2);
+					},
+					_ => names.push(arg),
+				}
+			}
+
+			// `-r` forgets every location, reading PATH afresh
+			state.refresh_path_index();
+			if reset {
+				state.path_index.clear();
+				state.hash_hits.clear();
+			}
+			// Naming commands looks them up again, and remembers them with no hits
+			let mut status = 0;
+			for name in &names {
+				if state.is_builtin(name) || state.functions.contains_key(*name) {
+					continue;
+				}
+				match state.find_command(name) {
+					Some(_) => {
+						state.hash_hits.insert(name.to_string(), 0);
+					},
+					None => {
+						diagnostics::error_to(redirects.get(&2), format_args!("hash: {name}: not found"))?;
+						status = 1;
+					},
+				}
+			}
+
+			if names.is_empty() && !reset {
+				if state.hash_hits.is_empty() {
+					diagnostics::error_to(redirects.get(&2), "hash: hash table empty")?;
+				} else {
+					let mut out = writer_for_fd(&redirects, 1)?;
+					let mut hashed: Vec<_> = state.hash_hits.iter().collect();
+					hashed.sort();
+					writeln!(out, "hits\tcommand")?;
+					for (name, hits) in hashed {
+						let path = state.path_index.find(name).map(|path| path.display().to_string()).unwrap_or_default();
+						writeln!(out, "{hits:4}\t{path}")?;
+					}
+				}
+			}
+			status
+		},
+
+		"eval" => {
+			let source = argv.collect::<Vec<_>>().join(" ");
+			match parse_to_end(&source, &state.aliases) {
+				Ok(commands) => {
+					state.last_status = 0; // What an empty command leaves
+					// `break`, `continue` and `return` inside act on the loops and
+					// function around `eval`
+					state.flow = with_redirects(&redirects, || run_command_list(commands, state))??;
+					state.last_status
+				},
+				Err(e) => {
+					diagnostics::error_to(redirects.get(&2), format_args!("eval: {e}"))?;
+					2
+				},
+			}
+		},
+
+		"exec" => {
+			// The redirections stay in effect for the shell itself, and for
+			// the command that replaces it
+			io::stdout().flush()?;
+			for (fd, file) in redirects {
+				let fd = i32::from(fd);
+				if file.as_raw_fd() == fd {
+					// Opened right where it belongs: keep it open past `file`
+					sys::set_inheritable(file.into_raw_fd())?;
+				} else {
+					sys::dup_onto(file.as_raw_fd(), fd)?;
+				}
+			}
+			let args: Vec<&str> = argv.collect();
+			let Some((&name, args)) = args.split_first() else {
+				return Ok(0);
+			};
+			let Some(path) = state.find_command(name) else {
+				diagnostics::error(format_args!("exec: {name}: not found"));
+				return Ok(127);
+			};
+			let mut command = Command::new(path);
+			if state.job_control {
+				// The shell ignores SIGTTOU to take the terminal back from jobs
+				unsafe { command.pre_exec(|| sys::reset_signal(sys::SIGTTOU)) };
+			}
+			// Only returns when the command couldn't be started
+			let e = command.arg0(name).args(args).envs(assignments).exec();
+			diagnostics::error(format_args!("exec: {name}: {e}"));
+			126
+		},
+
+		"kill" => {
+			let args: Vec<&str> = argv.collect();
+			let usage = "kill: usage: kill [-s sigspec | -n signum | -sigspec] pid | jobspec ... or kill -l [sigspec]";
+			let (signal_spec, targets) = match args.as_slice() {
+				["-l" | "-L", specs @ ..] => {
+					let mut out = writer_for_fd(&redirects, 1)?;
+					if specs.is_empty() {
+						list_signals(&mut out)?;
+						return Ok(0);
+					}
+					// Numbers, including exit statuses of 128+N, are named and names numbered
+					let mut status = 0;
+					for spec in specs {
+						let found = match spec.parse::<i32>() {
+							Ok(number) => sys::signal_name(if number > 128 { number - 128 } else { number }).map(str::to_owned),
+							Err(_) => sys::signal_number(spec).filter(|n| *n != 0).map(|n| n.to_string()),
+						};
+						match found {
+							Some(text) => writeln!(out, "{text}")?,
+							None => {
+								diagnostics::error_to(redirects.get(&2), format_args!("kill: {spec}: invalid signal specification"))?;
+								status = 1;
+							},
+						}
+					}
+					return Ok(status);
+				},
+				["-s" | "-n", spec, targets @ ..] => (*spec, targets),
+				[option @ ("-s" | "-n")] => {
+					diagnostics::error_to(redirects.get(&2), format_args!("kill: {option}: option requires an argument"))?;
+					return Ok(2);
+				},
+				[option, targets @ ..] if option.len() > 1 && option.starts_with('-') && *option != "--" => (&option[1..], targets),
+				targets => ("TERM", targets),
+			};
+			let targets = match targets {
+				["--", rest @ ..] => rest,
+				_ => targets,
+			};
+			let Some(signal_number) = sys::signal_number(signal_spec) else {
+				diagnostics::error_to(redirects.get(&2), format_args!("kill: {signal_spec}: invalid signal specification"))?;
+				return Ok(1);
+			};
+			if targets.is_empty() {
+				diagnostics::error_to(redirects.get(&2), usage)?;
+				return Ok(2);
+			}
+
+			let mut status = 0;
+			for target in targets {
+				let (pid, job) = if target.starts_with('%') {
+					match state.jobs.find(target) {
+						Ok(job) => (job.pid, true),
+						Err(e) => {
+							diagnostics::error_to(redirects.get(&2), format_args!("kill: {e}"))?;
+							status = 1;
+							continue;
+						},
+					}
+				} else if let Ok(pid) = target.parse::<sys::Pid>() {
+					(pid, false)
+				} else {
+					diagnostics::error_to(redirects.get(&2), format_args!("kill: {target}: arguments must be process or job IDs"))?;
+					status = 1;
+					continue;
+				};
+				let sent = if job { state.signal_job(pid, signal_number) } else { sys::send_signal(pid, signal_number) };
+				match sent {
+					Ok(()) => {
+						// A stopped job has to be continued to act on a signal to end it
+						if job && [sys::SIGTERM, sys::SIGHUP].contains(&signal_number) {
+							state.signal_job(pid, sys::SIGCONT).ok();
+						}
+					},
+					Err(e) => {
+						let problem = match e.kind() {
+							io::ErrorKind::PermissionDenied => "Operation not permitted",
+							_ => "No such process",
+						};
+						diagnostics::error_to(redirects.get(&2), format_args!("kill: ({pid}) - {problem}"))?;
+						status = 1;
+					},
+				}
+			}
+			status
+		},
+
+		"wait" => {
+			// Without arguments, wait for every job running in the background,
+			// and succeed whatever their statuses
+			let waiting_for_all = argv.clone().next().is_none();
+			let targets: Vec<String> = if waiting_for_all {
+				state.jobs.iter().filter(|job| job.state == JobState::Running).map(|job| format!("%{}", job.id)).collect()
+			} else {
+				argv.map(str::to_owned).collect()
+			};
+			let mut status = 0;
+			for target in &targets {
+				let pid = if target.starts_with('%') {
+					match state.jobs.find(target) {
+						Ok(job) => job.pid,
+						Err(e) => {
+							diagnostics::error_to(redirects.get(&2), format_args!("wait: {e}"))?;
+							status = 127;
+							continue;
+						},
+					}
+				} else if let Ok(pid) = target.parse::<sys::Pid>() {
+					pid
+				} else {
+					diagnostics::error_to(redirects.get(&2), format_args!("wait: `{target}': not a pid or valid job spec"))?;
+					status = 2;
+					continue;
+				};
+
+				let job = state.jobs.with_pid(pid).map(|job| (job.id, job.state));
+				// A job that finished before now was already reaped
+				let waited = match job {
+					Some((_, JobState::Done(exit))) => Ok(exit),
+					_ => sys::wait_for_exit_or_stop(pid),
+				};
+				let job = job.map(|(id, _)| id);
+				match waited {
+					Ok(exit) if exit.stopped_signal().is_some() => {
+						// It was stopped rather than finishing, so stays a job
+						if let Some(job) = job.and_then(|id| state.jobs.get_mut(id)) {
+							job.state = JobState::Stopped;
+						}
+						status = exit_code(exit);
+					},
+					Ok(exit) => {
+						if let Some(id) = job {
+							state.jobs.remove(id);
+						}
+						status = exit_code(exit);
+					},
+					Err(_) => {
+						if let Some(id) = job {
+							state.jobs.remove(id);
+						}
+						diagnostics::error_to(redirects.get(&2), format_args!("wait: pid {pid} is not a child of this shell"))?;
+						status = 127;
+					},
+				}
+			}
+			if waiting_for_all { 0 } else { status }
+		},
+
+		"trap" => {
+			let args: Vec<&str> = argv.collect();
+			// The signal a spec names: a number, a name with or without SIG, or EXIT
+			let signal_of = |spec: &str| match spec.to_ascii_uppercase().as_str() {
+				"EXIT" | "0" => Some(0),
+				_ => sys::signal_number(spec).filter(|n| *n != 0),
+			};
+			// `--` ends the options, so an action may start with `-`
+			let (options, args) = match args.split_first() {
+				Some((&"--", rest)) => (None, rest),
+				Some((option, rest)) if option.starts_with('-') && option.len() > 1 => (Some(*option), rest),
+				_ => (None, args.as_slice()),
+			};
+			let (action, specs) = match (options, args) {
+				(None, []) | (Some("-p"), _) => {
+					// Commands that set the traps again, for all of them or the given signals
+					let mut out = writer_for_fd(&redirects, 1)?;
+					let mut status = 0;
+					let mut shown = Vec::new();
+					for spec in args {
+						match signal_of(spec) {
+							Some(signal) => shown.push(signal),
+							None => {
+								diagnostics::error_to(redirects.get(&2), format_args!("trap: {spec}: invalid signal specification"))?;
+								status = 1;
+							},
+						}
+					}
+					let mut traps: Vec<_> = state.traps.iter().filter(|(signal, _)| args.is_empty() || shown.contains(signal)).collect();
+					traps.sort();
+					for (&signal, action) in traps {
+						let name = sys::signal_name(signal).map_or("EXIT".to_owned(), |name| format!("SIG{name}"));
+						writeln!(out, "trap -- {} {name}", expand::quote(action))?;
+					}
+					return Ok(status);
+				},
+				(Some("-l"), _) => {
+					list_signals(&mut writer_for_fd(&redirects, 1)?)?;
+					return Ok(0);
+				},
+				(Some(option), _) => {
+					diagnostics::error_to(redirects.get(&2), format_args!("trap: {option}: invalid option"))?;
+					diagnostics::error_to(redirects.get(&2), "trap: usage: trap [-lp] [[arg] signal_spec ...]")?;
+					return Ok(2);
+				},
+				// A lone signal is reset, like one after `-`
+				(None, [spec]) => ("-", std::slice::from_ref(spec)),
+				(None, [action, specs @ ..]) => (*action, specs),
+			};
+
+			let mut status = 0;
+			for spec in specs {
+				let Some(signal) = signal_of(spec) else {
+					diagnostics::error_to(redirects.get(&2), format_args!("trap: {spec}: invalid signal specification"))?;
+					status = 1;
+					continue;
+				};
+				let handled = match action {
+					"-" => {
+						state.traps.remove(&signal);
+						// An interactive shell goes back to surviving Ctrl-C and Ctrl-Z
+						match signal {
+							0 => Ok(()),
+							sys::SIGINT | sys::SIGTSTP if state.interactive => sys::survive_signal(signal),
+							sys::SIGHUP if state.interactive => sys::catch_signal(signal),
+							sys::SIGCHLD => sys::catch_signal(signal),
+							_ => sys::reset_signal(signal),
+						}
+					},
+					_ => {
+						state.traps.insert(signal, action.to_owned());
+						match signal {
+							0 => Ok(()),
+							_ if action.is_empty() => sys::ignore_signal_entirely(signal),
+							_ => sys::catch_signal(signal),
+						}
+					},
+				};
+				if let Err(e) = handled {
+					diagnostics::error_to(redirects.get(&2), format_args!("trap: {spec}: {e}"))?;
+					status = 1;
+				}
+			}
+			status
+		},
+
+		"jobs" => {
+			let mut show_pid = false;
+			let mut pids_only = false;
+			let mut specs = Vec::new();
+			for arg in argv {
+				match arg {
+					"-l" => show_pid = true,
+					"-p" => pids_only = true,
+					_ if arg.starts_with('-') && arg.len() > 1 => {
+						diagnostics::error_to(redirects.get(&2), format_args!("jobs: {arg}: invalid option"))?;
+						return Ok(2);
+					},
+					_ => specs.push(arg),
+				}
+			}
+
+			state.jobs.update();
+			let mut status = 0;
+			let mut ids = Vec::new();
+			if specs.is_empty() {
+				ids.extend(state.jobs.iter().map(|job| job.id));
+			}
+			for spec in specs {
+				match state.jobs.find(spec) {
+					Ok(job) => ids.push(job.id),
+					Err(e) => {
+						diagnostics::error_to(redirects.get(&2), format_args!("jobs: {e}"))?;
+						status = 1;
+					},
+				}
+			}
+			let mut out = writer_for_fd(&redirects, 1)?;
+			for &id in &ids {
+				let Some(job) = state.jobs.get(id) else { continue };
+				if pids_only {
+					writeln!(out, "{}", job.pid)?;
+				} else {
+					writeln!(out, "{}", state.jobs.describe(job, show_pid))?;
+				}
+			}
+			// Finished jobs are reported once
+			for id in ids {
+				if state.jobs.get(id).is_some_and(|job| matches!(job.state, JobState::Done(_))) {
+					state.jobs.remove(id);
+				}
+			}
+			status
+		},
+
+		"fg" | "bg" => {
+			state.jobs.update();
+			// Without a jobspec, the current job
+			let specs: Vec<&str> = argv.collect();
+			let found: Vec<Result<usize, String>> = if specs.is_empty() {
+				vec![state.jobs.find("%+").map(|job| job.id).map_err(|_| "current: no such job".to_owned())]
+			} else if cmd == "fg" {
+				vec![state.jobs.find(specs[0]).map(|job| job.id)]
+			} else {
+				specs.iter().map(|spec| state.jobs.find(spec).map(|job| job.id)).collect()
+			};
+
+			let mut status = 0;
+			for id in found {
+				let id = match id {
+					Ok(id) => id,
+					Err(e) => {
+						diagnostics::error_to(redirects.get(&2), format_args!("{cmd}: {e}"))?;
+						status = 1;
+						continue;
+					},
+				};
+				let Some(job) = state.jobs.get(id).cloned() else { continue };
+				match job.state {
+					JobState::Done(_) => {
+						state.jobs.remove(id);
+						diagnostics::error_to(redirects.get(&2), format_args!("{cmd}: job has terminated"))?;
+						status = 1;
+						continue;
+					},
+					JobState::Running if cmd == "bg" => {
+						diagnostics::error_to(redirects.get(&2), format_args!("bg: job {id} already in background"))?;
+						continue;
+					},
+					_ => {},
+				}
+
+				let mut out = writer_for_fd(&redirects, 1)?;
+				if cmd == "bg" {
+					writeln!(out, "[{id}]{} {} &", state.jobs.marker(&job), job.command)?;
+				} else {
+					writeln!(out, "{}", job.command)?;
+				}
+				out.flush()?;
+				drop(out);
+				if cmd == "fg" {
+					state.give_terminal(job.pid);
+				}
+				if let Err(e) = state.signal_job(job.pid, sys::SIGCONT) {
+					state.take_terminal(ExitStatus::default());
+					diagnostics::error_to(redirects.get(&2), format_args!("{cmd}: {e}"))?;
+					status = 1;
+					continue;
+				}
+				if let Some(job) = state.jobs.get_mut(id) {
+					job.state = JobState::Running;
+				}
+				if cmd == "bg" {
+					continue;
+				}
+
+				// In the foreground, wait for it to finish or stop again
+				let exit = sys::wait_for_exit_or_stop(job.pid)?;
+				state.take_terminal(exit);
+				if exit.stopped_signal().is_some() {
+					if let Some(job) = state.jobs.get_mut(id) {
+						job.state = JobState::Stopped;
+					}
+					if let Some(job) = state.jobs.get(id) {
+						println!("\n{}", state.jobs.describe(job, false));
+					}
+				} else {
+					state.jobs.remove(id);
+					report_termination(exit);
+				}
+				status = exit_code(exit);
+			}
+			status
+		},
+
+		"disown" => {
+			let mut no_hup = false;
+			let mut all = false;
+			let mut running_only = false;
+			let mut specs = Vec::new();
+			for arg in argv {
+				match arg {
+					"-h" => no_hup = true,
+					"-a" => all = true,
+					"-r" => running_only = true,
+					_ if arg.starts_with('-') && arg.len() > 1 => {
+						diagnostics::error_to(redirects.get(&2), format_args!("disown: {arg}: invalid option"))?;
+						return Ok(2);
+					},
+					_ => specs.push(arg),
+				}
+			}
+
+			let mut status = 0;
+			let mut ids = Vec::new();
+			if all || running_only {
+				ids.extend(state.jobs.iter().filter(|job| !running_only || job.state == JobState::Running).map(|job| job.id));
+			} else if specs.is_empty() {
+				match state.jobs.find("%+") {
+					Ok(job) => ids.push(job.id),
+					Err(_) => {
+						diagnostics::error_to(redirects.get(&2), "disown: current: no such job")?;
+						status = 1;
+					},
+				}
+			}
+			for spec in specs {
+				match state.jobs.find(spec) {
+					Ok(job) => ids.push(job.id),
+					Err(e) => {
+						diagnostics::error_to(redirects.get(&2), format_args!("disown: {e}"))?;
+						status = 1;
+					},
+				}
+			}
+			// The processes carry on; the shell just stops keeping track of them
+			for id in ids {
+				if no_hup {
+					if let Some(job) = state.jobs.get_mut(id) {
+						job.no_hup = true;
+					}
+				} else {
+					state.jobs.disown(id);
+				}
+			}
+			status
+		},
+
+		"break" | "continue" => {
+			let arg = argv.next();
+			if state.loop_depth == 0 {
+				diagnostics::error_to(redirects.get(&2), format_args!("{cmd}: only meaningful in a `for', `while', or `until' loop"))?;
+				0
+			} else {
+				match arg.map_or(Ok(1), str::parse::<usize>) {
+					Ok(0) => {
+						diagnostics::error_to(redirects.get(&2), format_args!("{cmd}: 0: loop count out of range"))?;
+						1
+					},
+					Ok(levels) => {
+						// More levels than there are loops leaves them all
+						let levels = levels.min(state.loop_depth);
+						state.flow = if cmd == "break" { Flow::Break(levels) } else { Flow::Continue(levels) };
+						0
+					},
+					Err(_) => {
+						diagnostics::error_to(redirects.get(&2), format_args!("{cmd}: {}: numeric argument required", arg.unwrap_or_default()))?;
+						1
+					},
+				}
+			}
+		},
+
+		"return" => {
+			let args: Vec<&str> = argv.collect();
+			if state.call_depth == 0 {
+				diagnostics::error_to(redirects.get(&2), "return: can only `return' from a function or sourced script")?;
+				1
+			} else if args.len() > 1 {
+				diagnostics::error_to(redirects.get(&2), "return: too many arguments")?;
+				2
+			} else {
+				state.flow = Flow::Return;
+				match args.first().map(|n| n.parse::<i64>()) {
+					None => state.last_status,
+					Some(Ok(n)) => (n & 0xff) as i32, // Statuses wrap around like exit codes
+					Some(Err(_)) => {
+						diagnostics::error_to(redirects.get(&2), format_args!("return: {}: numeric argument required", args[0]))?;
+						2
+					},
+				}
+			}
+		},
+
+		// Handle external commands, i.e., commands not in the built-in list
+		_ => run_external(cmd, argv, assignments, redirects, state)?,
+	};
+
+	Ok(status)
+}
+
+/// Why the file at `path` can't be run as a command, with the status to
+/// fail with, or None when it can.
+fn unrunnable(path: &Path) -> Option<(&'static str, i32)> {
+	if !path.exists() {
+		Some(("No such file or directory", 127))
+	} else if path.is_dir() {
+		Some(("Is a directory", 126))
+	} else if !sys::can_access(path, sys::X_OK) {
+		Some(("Permission denied", 126))
+	} else {
+		None
+	}
+}
+
+/// Run the command `cmd`, from PATH or by its path, in a child process and
+/// wait for it, noting it as a job if it is stopped.
+fn run_external<'a>(cmd: &'a str, argv: impl Iterator<Item = &'a str> + Clone, assignments: Vec<(String, String)>, redirects: HashMap<u8, File>, state: &mut ShellState) -> Result<i32, Box<dyn Error>> {
+	let found = state.find_command(cmd);
+	if path_index::is_path(cmd) {
+		if let Some((problem, status)) = unrunnable(Path::new(cmd)) {
+			diagnostics::error_to(redirects.get(&2), format_args!("{cmd}: {problem}"))?;
+			return Ok(status);
+		}
+	} else if found.is_none() {
+		// A file on PATH that just can't be executed is a different failure
+		if let Some(file) = state.path_index.dirs().iter().map(|dir| Path::new(dir).join(cmd)).find(|file| file.is_file()) {
+			diagnostics::error_to(redirects.get(&2), format_args!("{}: Permission denied", file.display()))?;
+			return Ok(126);
+		}
+	}
+	let status = if let Some(path) = found {
+		if !path_index::is_path(cmd) {
+			*state.hash_hits.entry(cmd.to_owned()).or_insert(0) += 1;
+		}
+		let mut child = Command::new(path);
+		child.arg0(cmd);
+		let command_line = std::iter::once(cmd).chain(argv.clone()).collect::<Vec<_>>().join(" ");
+
+		child.args(argv)                     
+			.envs(assignments)
+			.stdin(Stdio::inherit()) 
+			.stderr(Stdio::inherit());
+		
+		// Fds other than the standard three have no Stdio slot, so they
+		// are dup'ed into place in the child between fork and exec
+		let mut extra_fds: Vec<(File, u8)> = Vec::new();
+
+		for (fd, file) in redirects {
+			// Match the file descriptor to set the appropriate stream
+			// 0 for stdin, 1 for stdout, 2 for stderr
+			match fd {
+				0 => { child.stdin(Stdio::from(file)); }
+				1 => { child.stdout(Stdio::from(file)); }
+				2 => { child.stderr(Stdio::from(file)); }
+				_ => extra_fds.push((file, fd)),
+			}
+		}
+
+		let job_control = state.job_control;
+		if !extra_fds.is_empty() || job_control {
+			let mapping: Vec<(i32, i32)> = extra_fds
+				.iter()
+				.map(|(file, fd)| (file.as_raw_fd(), i32::from(*fd)))
+				.collect();
+			unsafe {
+				child.pre_exec(move || {
+					for &(src, target) in &mapping {
+						sys::dup_onto(src, target)?;
+					}
+					if job_control {
+						// The shell hands over the terminal too, but the command
+						// mustn't get to read it first. Its stdin may not be the
+						// terminal any more, which the shell's handing over covers
+						sys::set_process_group(0, 0)?;
+						sys::set_foreground_group(0, sys::process_group()).ok();
+						sys::reset_signal(sys::SIGTTOU)?;
+					}
+					Ok(())
+				});
+			}
+		}
+		
+		let spawned = child.spawn();
+		drop(child); // Close our copies of the redirected files
+		match spawned.and_then(|process| wait_for_foreground(process.id() as sys::Pid, command_line, state)) {
+			Ok(status) => exit_code(status),
+			Err(e) => {
+				diagnostics::error(format_args!("{cmd}: {e}"));
+				126
+			}
+		}
+	} else {
+		diagnostics::error_to(redirects.get(&2), format_args!("{cmd}: command not found"))?;
+		127
+	};
+	Ok(status)
+}
+
+/// Wait for the foreground job led by `pid`, which runs `command`, with the
+/// terminal handed to it. Under job control we also notice when Ctrl-Z
+/// stops it, and note it as a job; otherwise it is waited for until it
+/// finishes, as a shell running a job does for the commands in it.
+fn wait_for_foreground(pid: sys::Pid, command: String, state: &mut ShellState) -> io::Result<ExitStatus> {
+	state.give_terminal(pid);
+	let status = if state.job_control { sys::wait_for_exit_or_stop(pid) } else { sys::wait_for(pid) };
+	if let Ok(status) = status {
+		state.take_terminal(status);
+		if status.stopped_signal().is_some() {
+			let id = state.jobs.add(pid, command, JobState::Stopped);
+			if let Some(job) = state.jobs.get(id) {
+				println!("\n{}", state.jobs.describe(job, false));
+			}
+		} else {
+			report_termination(status);
+		}
+	}
+	status
+}
+
+/// Say which signal killed a foreground command, if one did, as bash does.
+/// Ctrl-C's interrupt and a closed pipe go unmentioned, being expected.
+fn report_termination(status: ExitStatus) {
+	match status.signal() {
+		Some(sys::SIGINT) => println!(), // Move past the ^C the terminal echoed
+		Some(sys::SIGPIPE) | None => {},
+		Some(_) => {
+			let _ = io::stdout().flush();
+			eprintln!("{}", sys::termination_message(status).unwrap_or_default());
+		},
+	}
+}
+
+/// Write the signals `kill -l` and `trap -l` list, five to a line.
+fn list_signals(out: &mut impl Write) -> io::Result<()> {
+	for (i, name) in sys::SIGNAL_NAMES.iter().enumerate() {
+		let end = if i % 5 == 4 || i + 1 == sys::SIGNAL_NAMES.len() { '\n' } else { '\t' };
+		write!(out, "{:2}) SIG{name}{end}", i + 1)?;
+	}
+	Ok(())
+}
+
+/// Whether `name` can be defined with `alias`: a word with no quotes,
+/// expansions, slashes, `=` or characters that end a word.
+fn is_valid_alias_name(name: &str) -> bool {
+	!name.is_empty() && !name.contains(|c: char| c.is_whitespace() || "/$`=\\'\"|&;()<>".contains(c))
+}
+
+/// Make `path` the working directory, keeping PWD and OLDPWD up to date.
+fn change_dir(path: &Path, state: &mut ShellState) -> io::Result<()> {
+	env::set_current_dir(path)?;
+	let old = state.vars.get("PWD").unwrap_or_default().to_owned();
+	state.vars.export("OLDPWD", Some(&old));
+	state.vars.export("PWD", Some(&path.to_string_lossy()));
+	Ok(())
+}
+
+/// The directory stack as `dirs` lists it: the working directory, then
+/// those saved by `pushd`, most recent first.
+fn dir_stack(state: &ShellState) -> Vec<PathBuf> {
+	let cwd = env::current_dir().unwrap_or_default();
+	std::iter::once(cwd).chain(state.dir_stack.iter().cloned()).collect()
+}
+
+/// The position in a directory stack of `len` entries that `+N` or `-N`
+/// refers to, counting from the left or the right of the `dirs` listing.
+/// None when `arg` isn't of that form.
+fn stack_index(arg: &str, len: usize) -> Option<Result<usize, String>> {
+	let n: usize = arg.strip_prefix(['+', '-'])?.parse().ok()?;
+	let index = if arg.starts_with('+') { Some(n) } else { len.checked_sub(n + 1) };
+	Some(index.filter(|&i| i < len).ok_or_else(|| format!("{arg}: directory stack index out of range")))
+}
+
+/// How `dirs` lays out the directory stack.
+#[derive(Clone, Copy)]
+enum DirsFormat {
+	Line, // All on one line
+	PerLine, // -p: one per line
+	Numbered, // -v: one per line, with its position
+}
+
+/// Print the directory stack, with `$HOME` abbreviated to `~` unless `long`.
+fn print_dir_stack(state: &ShellState, out: &mut dyn Write, format: DirsFormat, long: bool) -> io::Result<()> {
+	let entries = dir_stack(state).iter().map(|dir| match long {
+		true => dir.to_string_lossy().into_owned(),
+		false => prompt::abbreviate_home(&dir.to_string_lossy(), state),
+	}).collect::<Vec<_>>();
+	match format {
+		DirsFormat::Line => writeln!(out, "{}", entries.join(" ")),
+		DirsFormat::PerLine => entries.iter().try_for_each(|entry| writeln!(out, "{entry}")),
+		DirsFormat::Numbered => entries.iter().enumerate().try_for_each(|(i, entry)| writeln!(out, "{i:2}  {entry}")),
+	}
+}
+
+/// Turn the option called `name`, as in `set -o name`, on or off. Returns
+/// false when there is no such option.
+fn set_named_option(state: &mut ShellState, name: &str, on: bool) -> bool {
+	match (name, on) {
+		// Turning one editing mode off switches to the other
+		("emacs", true) | ("vi", false) => state.editing_mode = EditingMode::Emacs,
+		("vi", true) | ("emacs", false) => state.editing_mode = EditingMode::Vi,
+		_ => return state.options.set(name, on),
+	}
+	true
+}
+
+/// Show a command about to run on stderr for `set -x`: PS4, then its
+/// expanded assignments and words, quoted where needed.
+fn trace_command(assignments: &[(String, String)], argv: &[String], state: &ShellState) {
+	let mut line = state.vars.get("PS4").unwrap_or(prompt::DEFAULT_PS4).to_owned();
+	let assignments = assignments.iter().map(|(name, value)| format!("{name}={}", expand::quote_if_needed(value)));
+	let words = argv.iter().map(|word| expand::quote_if_needed(word));
+	line.push_str(&assignments.chain(words).collect::<Vec<_>>().join(" "));
+	let _ = writeln!(io::stderr(), "{line}");
+}
+
+/// Run the commands of a list in order, short-circuiting `&&` and `||`.
+/// The status ends up in `state.last_status`; the flow says whether the list
+/// was cut short by `break`, `continue` or `return`.
+fn run_command_list(commands: CommandList, state: &mut ShellState) -> Result<Flow, Box<dyn Error>> {
+	let mut commands = commands.into_iter().peekable();
+	while let Some((connector, command)) = commands.next() {
+		// Short-circuit on the status of the previous command
+		let skip = match connector {
+			Connector::Always => false,
+			Connector::And => state.last_status != 0,
+			Connector::Or => state.last_status == 0,
+		};
+		if skip {
+			continue;
+		}
+
+		// For `set -e`, only simple commands, subshells, pipelines and `[[` fail, and not
+		// when `&&` or `||` tests their status. Compound commands report the
+		// failures of the commands inside them
+		let tested = commands.peek().is_some_and(|(next, _)| *next != Connector::Always);
+		let can_fail = !tested && matches!(command, ShellCommand::Simple(_) | ShellCommand::Subshell(_) | ShellCommand::Pipeline(_) | ShellCommand::Conditional(_));
+		let flow = if tested {
+			run_tested(state, |state| run_shell_command(command, state))?
+		} else {
+			run_shell_command(command, state)?
+		};
+		run_pending_traps(state);
+		if can_fail && state.last_status != 0 && state.options.errexit && state.condition_depth == 0 {
+			exit_shell(state, state.last_status);
+		}
+		if flow != Flow::Normal {
+			return Ok(flow);
+		}
+	}
+	Ok(Flow::Normal)
+}
+
+/// Run the stages of a pipeline in forked copies of the shell, each one's
+/// stdout connected to the next one's stdin, and wait for them all. Returns
+/// the status of the last stage, or with `set -o pipefail` that of the last
+/// stage to fail, if any did.
+fn run_pipeline(stages: Vec<ShellCommand>, state: &mut ShellState) -> Result<i32, Box<dyn Error>> {
+	let count = stages.len();
+	let mut input: Option<OwnedFd> = None; // The read end of the pipe from the stage before
+	let mut pids = Vec::new();
+	for (i, stage) in stages.into_iter().enumerate() {
+		let output = if i + 1 < count { Some(sys::pipe()?) } else { None };
+		pids.push(fork_shell(|| {
+			// Like the commands it runs, a stage writing to a pipe nobody reads
+			// any more should be ended by SIGPIPE, which Rust ignores
+			sys::reset_signal(sys::SIGPIPE)?;
+			if let Some(input) = &input {
+				sys::dup_onto(input.as_raw_fd(), 0)?;
+			}
+			if let Some((read_end, write_end)) = &output {
+				// Holding the read end too would keep a stage that never exits
+				// from seeing the next one go away
+				sys::close_fd(read_end.as_raw_fd())?;
+				sys::dup_onto(write_end.as_raw_fd(), 1)?;
+			}
+			run_shell_command(stage, state)?;
+			exit_shell(state, state.last_status)
+		})?);
+		// Only the stages may hold the write ends, or readers never see the end
+		input = output.map(|(read_end, _)| read_end);
+	}
+
+	let mut status = 0;
+	for pid in pids {
+		let exit = sys::wait_for(pid)?;
+		report_termination(exit);
+		let code = exit_code(exit);
+		if code != 0 || !state.options.pipefail {
+			status = code;
+		}
+	}
+	Ok(status)
+}
+
+/// Call `run` for commands whose status is tested, by `if`, `while`, `!`,
+/// `&&` or `||`, so no failure inside them triggers `set -e`.
+fn run_tested<T>(state: &mut ShellState, run: impl FnOnce(&mut ShellState) -> T) -> T {
+	state.condition_depth += 1;
+	let result = run(state);
+	state.condition_depth -= 1;
+	result
+}
+
+/// The commands of `list` written out for `jobs`: simple commands with their
+/// words, compound commands by the word they start with.
+fn list_text(list: &CommandList) -> String {
+	let mut text = String::new();
+	for (i, (connector, command)) in list.iter().enumerate() {
+		if i > 0 {
+			text.push_str(match connector {
+				Connector::Always => "; ",
+				Connector::And => " && ",
+				Connector::Or => " || ",
+			});
+		}
+		text.push_str(&command_text(command));
+	}
+	text
+}
+
+/// One command of a list written out, for `list_text`.
+fn command_text(command: &ShellCommand) -> String {
+	match command {
+		ShellCommand::Simple(simple) => simple.assignments
+			.iter()
+			.map(|(name, value)| format!("{name}={value}"))
+			.chain(simple.argv.iter().cloned())
+			.collect::<Vec<_>>()
+			.join(" "),
+		ShellCommand::Subshell(body) => format!("( {} )", list_text(body)),
+		ShellCommand::Background(body) => format!("{} &", list_text(body)),
+		ShellCommand::Group(body) => format!("{{ {}; }}", list_text(body)),
+		ShellCommand::Redirected(command, _) => command_text(command),
+		ShellCommand::Not(command) => format!("! {}", command_text(command)),
+		ShellCommand::Pipeline(stages) => stages.iter().map(command_text).collect::<Vec<_>>().join(" | "),
+		ShellCommand::FunctionDef(name, _) => format!("{name}() {{ ... }}"),
+		ShellCommand::If { .. } => "if ...".to_owned(),
+		ShellCommand::For { name, .. } => format!("for {name} ..."),
+		ShellCommand::While { until, .. } => if *until { "until ..." } else { "while ..." }.to_owned(),
+		ShellCommand::Case { word, .. } => format!("case {word} ..."),
+		ShellCommand::Conditional(_) => "[[ ... ]]".to_owned(),
+	}
+}
+
+/// Run one command of a list, simple or compound, setting `state.last_status`.
+fn run_shell_command(command: ShellCommand, state: &mut ShellState) -> Result<Flow, Box<dyn Error>> {
+	match command {
+		ShellCommand::Simple(command) => {
+			state.last_status = run_command(command, state)?;
+			finish_process_substitutions();
+			// `break`, `continue` and `return` leave their request behind
+			Ok(std::mem::replace(&mut state.flow, Flow::Normal))
+		},
+		ShellCommand::Subshell(body) => {
+			// Nothing the commands change, from variables to the working
+			// directory, can reach this shell. Nor can `break` or `return`
+			let text = format!("( {} )", list_text(&body));
+			let pid = fork_shell(|| {
+				state.start_job(false)?;
+				state.reset_traps();
+				run_command_list(body, state)?;
+				exit_shell(state, state.last_status)
+			})?;
+			state.last_status = exit_code(wait_for_foreground(pid, text, state)?);
+			Ok(Flow::Normal)
+		},
+		ShellCommand::Pipeline(stages) => {
+			// The stages are run by a forked copy of the shell, which leads the
+			// job they make up, so they stop and are continued together
+			let text = stages.iter().map(command_text).collect::<Vec<_>>().join(" | ");
+			let pid = fork_shell(|| {
+				state.start_job(false)?;
+				state.reset_traps();
+				let status = run_pipeline(stages, state)?;
+				exit_shell(state, status)
+			})?;
+			state.last_status = exit_code(wait_for_foreground(pid, text, state)?);
+			Ok(Flow::Normal)
+		},
+		ShellCommand::Background(body) => {
+			let text = list_text(&body);
+			let pid = fork_shell(|| {
+				state.start_job(true)?;
+				state.reset_traps();
+				run_command_list(body, state)?;
+				exit_shell(state, state.last_status)
+			})?;
+			sys::set_process_group(pid, pid).ok(); // In case the child hasn't yet
+			let id = state.jobs.add(pid, text, JobState::Running);
+			if state.interactive {
+				eprintln!("[{id}] {pid}");
+			}
+			state.last_background = Some(pid);
+			state.last_status = 0;
+			Ok(Flow::Normal)
+		},
+		ShellCommand::Group(body) => run_command_list(body, state),
+		ShellCommand::Conditional(expr) => {
+			state.last_status = match expr.evaluate(state) {
+				Ok(true) => 0,
+				Ok(false) => 1,
+				Err(e) => {
+					diagnostics::error(e);
+					2
+				},
+			};
+			Ok(Flow::Normal)
+		},
+		ShellCommand::Not(command) => {
+			let flow = run_tested(state, |state| run_shell_command(*command, state))?;
+			state.last_status = if state.last_status == 0 { 1 } else { 0 };
+			Ok(flow)
+		},
+		ShellCommand::Redirected(command, redirects) => {
+			let mut redirected = ParsedCommand { assignments: Vec::new(), argv: Vec::new(), redirects };
+			let opened = expand_command(&mut redirected, state)
+				.and_then(|_| open_redirects(&redirected.redirects).map_err(|e| e.to_string()));
+			match opened {
+				Ok(opened) => Ok(with_redirects(&opened, || run_shell_command(*command, state))??),
+				Err(e) => {
+					diagnostics::error(e);
+					state.last_status = 1;
+					Ok(Flow::Normal)
+				},
+			}
+		},
+		ShellCommand::FunctionDef(name, body) => {
+			state.functions.insert(name, Rc::new(body));
+			state.last_status = 0;
+			Ok(Flow::Normal)
+		},
+		ShellCommand::If { branches, otherwise } => {
+			for (condition, body) in branches {
+				let flow = run_tested(state, |state| run_command_list(condition, state))?;
+				if flow != Flow::Normal {
+					return Ok(flow);
+				}
+				if state.last_status == 0 {
+					return run_command_list(body, state);
+				}
+			}
+			match otherwise {
+				Some(body) => run_command_list(body, state),
+				None => {
+					// With no branch taken and no `else`, the status is 0
+					state.last_status = 0;
+					Ok(Flow::Normal)
+				},
+			}
+		},
+		ShellCommand::For { name, words, body } => {
+			let values = match words {
+				Some(words) => match expand::expand_words(&words, state) {
+					Ok(values) => values,
+					Err(e) => {
+						diagnostics::error(e);
+						state.last_status = 1;
+						return Ok(Flow::Normal);
+					},
+				},
+				None => state.positional.clone(),
+			};
+
+			state.loop_depth += 1;
+			let flow = run_for(&name, values, &body, state);
+			state.loop_depth -= 1;
+			flow
+		},
+		ShellCommand::While { until, condition, body } => {
+			state.loop_depth += 1;
+			let flow = run_while(until, &condition, &body, state);
+			state.loop_depth -= 1;
+			flow
+		},
+		ShellCommand::Case { word, clauses } => {
+			let word = match expand::expand_case_word(&word, state) {
+				Ok(word) => word,
+				Err(e) => {
+					diagnostics::error(e);
+					state.last_status = 1;
+					return Ok(Flow::Normal);
+				},
+			};
+			for (patterns, body) in clauses {
+				for pattern in patterns {
+					match expand::expand_case_pattern(&pattern, state) {
+						// Only the first matching clause runs
+						Ok(pattern) if expand::glob_match(&pattern, &word) => {
+							state.last_status = 0; // In case the body is empty
+							return run_command_list(body, state);
+						},
+						Ok(_) => {},
+						Err(e) => {
+							diagnostics::error(e);
+							state.last_status = 1;
+							return Ok(Flow::Normal);
+						},
+					}
+				}
+			}
+			state.last_status = 0;
+			Ok(Flow::Normal)
+		},
+	}
+}
+
+/// How a command list finished: by running to its end, or cut short on the
+/// way out to the loop or function that a `break`, `continue` or `return` is for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Flow {
+	Normal,
+	Break(usize), // Loops still to leave
+	Continue(usize), // Loops still to leave, the last of which goes on to its next iteration
+	Return,
+}
+
+/// What the innermost loop does with the flow its body finished with: keep
+/// going (None), or stop and pass the returned flow outwards.
+fn loop_flow(flow: Flow) -> Option<Flow> {
+	match flow {
+		Flow::Normal | Flow::Continue(1) => None,
+		Flow::Break(1) => Some(Flow::Normal),
+		Flow::Break(n) => Some(Flow::Break(n - 1)),
+		Flow::Continue(n) => Some(Flow::Continue(n - 1)),
+		Flow::Return => Some(Flow::Return),
+	}
+}
+
+/// Run the body of a `for` loop with `name` set to each value in turn.
+fn run_for(name: &str, values: Vec<String>, body: &CommandList, state: &mut ShellState) -> Result<Flow, Box<dyn Error>> {
+	state.last_status = 0; // A loop that never runs its body succeeds
+	for value in values {
+		state.vars.set(name, &value);
+		if let Some(flow) = loop_flow(run_command_list(body.clone(), state)?) {
+			return Ok(flow);
+		}
+	}
+	Ok(Flow::Normal)
+}
+
+/// Run the body of a `while` loop for as long as the condition succeeds, or
+/// of an `until` loop for as long as it fails.
+fn run_while(until: bool, condition: &CommandList, body: &CommandList, state: &mut ShellState) -> Result<Flow, Box<dyn Error>> {
+	// The status is the body's last, or 0 if it never ran
+	let mut status = 0;
+	loop {
+		if let Some(flow) = loop_flow(run_tested(state, |state| run_command_list(condition.clone(), state))?) {
+			return Ok(flow);
+		}
+		if (state.last_status == 0) == until {
+			break;
+		}
+		if let Some(flow) = loop_flow(run_command_list(body.clone(), state)?) {
+			return Ok(flow);
+		}
+		status = state.last_status;
+	}
+	state.last_status = status;
+	Ok(Flow::Normal)
+}
+
+/// Start running a function or sourced file. Loops outside it can't be
+/// broken out of from inside, so the caller's loop depth is put aside and returned.
+fn enter_call(state: &mut ShellState) -> usize {
+	state.call_depth += 1;
+	std::mem::take(&mut state.loop_depth)
+}
+
+/// Finish a function or sourced file started with `enter_call`.
+fn leave_call(state: &mut ShellState, caller_loops: usize) {
+	state.call_depth -= 1;
+	state.loop_depth = caller_loops;
+}
+
+/// Run a script or command string in the given shell and return the status
+/// of the last command run.
+fn run_source(source: &str, state: &mut ShellState) -> Result<i32, Box<dyn Error>> {
+	let mut lines = source.lines();
+	run_lines(&mut || Ok(lines.next().map(str::to_owned)), state)
+}
+
+/// Run commands a line at a time from `next_line`, without prompting, until
+/// it runs out. Lines continue while a quote, a here-document or a construct
+/// like `if` is open, or after a trailing backslash, like they do at the prompt.
+fn run_lines(next_line: &mut dyn FnMut() -> io::Result<Option<String>>, state: &mut ShellState) -> Result<i32, Box<dyn Error>> {
+	while let Some(line) = next_line()? {
+		let mut input = line;
+		let commands = loop {
+			match tokenize_input(&input) {
+				Ok(tokens) => match parse_command_list(tokens, &state.aliases) {
+					Err(ParseError::Incomplete) => input.push('\n'), // e.g. inside `{ ... }`
+					parsed => break parsed?,
+				},
+				Err(TokenizerState::BackSlashOutsideQuote | TokenizerState::BackSlashInDoubleQuote) => {
+					input.pop(); // A backslash-newline pair is removed entirely
+				},
+				Err(_) => input.push('\n'), // Inside quotes or a here-document, the newline is kept
+			}
+			match next_line() {
+				Ok(Some(line)) => input.push_str(&line),
+				Ok(None) => break parse_to_end(&input, &state.aliases)?,
+				Err(e) => {
+					// What was read so far is cut short there, like at the end
+					diagnostics::error(format_args!("read error: {e}"));
+					break parse_to_end(&input, &state.aliases)?;
+				},
+			}
+		};
+
+		// `return` in a sourced file ends the file
+		if run_command_list(commands, state)? == Flow::Return {
+			break;
+		}
+	}
+	Ok(state.last_status)
+}
+
+/// Run the script at `path`, as in `myshell script.sh`, then exit with its status.
+fn run_script(path: &str, state: &mut ShellState) -> ! {
+	match fs::read_to_string(path) {
+		Ok(source) => exit_with(run_source(&source, state), state),
+		Err(e) => {
+			diagnostics::error(format_args!("{path}: {e}"));
+			std::process::exit(127);
+		},
+	}
+}
+
+/// Exit with the status a script or command string finished with, or 2 if
+/// it couldn't be run to the end, e.g. because of a syntax error.
+fn exit_with(result: Result<i32, Box<dyn Error>>, state: &mut ShellState) -> ! {
+	let status = result.unwrap_or_else(|e| {
+		diagnostics::error(e);
+		2
+	});
+	exit_shell(state, status);
+}
+
+/// Exit the shell with `status`, first running the EXIT trap if one is set.
+fn exit_shell(state: &mut ShellState, status: i32) -> ! {
+	// The trap goes first so an `exit` inside it doesn't run it again
+	if let Some(action) = state.traps.remove(&0).filter(|action| !action.is_empty()) {
+		state.last_status = status;
+		if let Err(e) = run_source(&action, state) {
+			diagnostics::error(e);
+		}
+	}
+	let _ = io::stdout().flush();
+	std::process::exit(status);
+}
+
+/// Run the traps of the signals that arrived since they were last checked,
+/// leaving `$?` as it was. SIGCHLD also has the jobs that finished reaped
+/// and their statuses recorded, to report before the next prompt.
+fn run_pending_traps(state: &mut ShellState) {
+	for signal in sys::take_pending_signals() {
+		if signal == sys::SIGCHLD {
+			state.jobs.update();
+		}
+		let Some(action) = state.traps.get(&signal).filter(|action| !action.is_empty()).cloned() else {
+			// Without a trap, an interactive shell only catches a hangup to
+			// pass it on to its jobs before exiting
+			if signal == sys::SIGHUP && state.interactive {
+				state.hang_up_jobs();
+				exit_shell(state, 128 + sys::SIGHUP);
+			}
+			continue;
+		};
+		let status = state.last_status;
+		if let Err(e) = run_source(&action, state) {
+			diagnostics::error(e);
+		}
+		state.last_status = status;
+	}
+}
+
+/// Set the terminal up for job control: jobs run in process groups of their
+/// own, and the one in the foreground is handed the terminal. Like bash, a
+/// shell started in the background first stops until it is brought to the
+/// foreground rather than take the terminal away. Fails when stdin isn't
+/// the shell's controlling terminal.
+fn enable_job_control() -> io::Result<()> {
+	while sys::foreground_group(0)? != sys::process_group() {
+		sys::signal_group(sys::process_group(), sys::SIGTTIN)?;
+	}
+	// Ignoring SIGTTOU lets the shell take the terminal back from jobs while
+	// it is in the background itself
+	sys::ignore_signal_entirely(sys::SIGTTOU)?;
+	sys::set_process_group(0, 0).ok(); // Already leading one when it leads the session
+	sys::set_foreground_group(0, sys::process_group()).inspect_err(|_| {
+		sys::reset_signal(sys::SIGTTOU).ok();
+	})
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+	let mut state = ShellState::new();
+	// Background jobs are reaped as they finish, rather than left as zombies
+	sys::catch_signal(sys::SIGCHLD)?;
+	let args = cli::parse_args(env::args().skip(1)).unwrap_or_else(|e| {
+		diagnostics::error(e);
+		std::process::exit(2);
+	});
+	if let Some(name) = args.name {
+		state.arg0 = name;
+	}
+	state.positional = args.positional;
+
+	match args.invocation {
+		cli::Invocation::Stdin if io::stdin().is_terminal() => {},
+		cli::Invocation::Stdin => {
+			// Commands piped in or read from a file: no prompts, and stop at the
+			// end. Lines are read a byte at a time, leaving the rest of the
+			// input to `read` and the commands run, as bash does
+			let result = run_lines(&mut || {
+				let (line, complete) = read::read_line(0, true)?;
+				Ok((complete || !line.is_empty()).then_some(line))
+			}, &mut state);
+			exit_with(result, &mut state);
+		},
+		cli::Invocation::Script(path) => run_script(&path, &mut state),
+		cli::Invocation::Command(source) => {
+			let result = run_source(&source, &mut state);
+			exit_with(result, &mut state);
+		},
+	}
+
+	let mut editor = LineEditor::new();
+	state.interactive = true;
+
+	if io::stdin().is_terminal() {
+		// Ctrl-C and Ctrl-Z should interrupt or stop the running command, not the shell
+		sys::survive_signal(sys::SIGINT)?;
+		sys::survive_signal(sys::SIGTSTP)?;
+		// A hangup ends the jobs too, unless they were disowned
+		sys::catch_signal(sys::SIGHUP)?;
+		match enable_job_control() {
+			Ok(()) => {
+				state.job_control = true;
+				state.terminal_mode = sys::terminal_mode(0).ok();
+			},
+			Err(e) => diagnostics::warning(format_args!("no job control in this shell: {e}")),
+		}
+	}
+
+	// Interactive sessions start with the user's settings from ~/.myshellrc
+	if let Some(home) = state.vars.get("HOME").filter(|home| !home.is_empty()) {
+		if let Ok(source) = fs::read_to_string(Path::new(home).join(".myshellrc")) {
+			if let Err(e) = run_source(&source, &mut state) {
+				diagnostics::error(e);
+			}
+		}
+	}
+
+	// Wait for user input
+    'prompt: loop {
+		run_pending_traps(&mut state);
+		// Say which background jobs finished while the last command ran
+		for line in state.jobs.take_finished() {
+			eprintln!("{line}");
+		}
+		// Prompt the user for a line of input; Ctrl-C discards it
+        let mut input = String::new();
+		// PROMPT_COMMAND runs before every prompt, leaving `$?` alone
+		if let Some(hook) = state.vars.get("PROMPT_COMMAND").map(str::to_owned) {
+			let status = state.last_status;
+			if let Err(e) = run_source(&hook, &mut state) {
+				diagnostics::error(e);
+			}
+			state.last_status = status;
+		}
+
+		// Completion and highlighting should know the commands on a new PATH
+		state.refresh_path_index();
+		let ps1 = prompt::prompt_from("PS1", prompt::DEFAULT_PS1, &state);
+		match editor.read_line(&ps1, &mut input, &state) {
+			Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+				// Ctrl-C, unless it was a signal for the top of the loop to handle
+				if !sys::signals_pending() {
+					state.last_status = 130;
+				}
+				continue;
+			},
+			Ok(0) => {
+				// End of input (Ctrl-D) acts like `exit`
+				if io::stdin().is_terminal() {
+					eprintln!("exit");
+				}
+				// Input also ends when the terminal hangs up
+				run_pending_traps(&mut state);
+				let status = state.last_status;
+				exit_shell(&mut state, status);
+			},
+			result => result?,
+		};
+
+		// Keep reading lines while a quote, a here-document or a construct like
+		// `if` is open, or the line ends in a backslash
+		let parsed = loop {
+			match tokenize_input(input.trim()) {
+				Ok(tokens) => match parse_command_list(tokens, &state.aliases) {
+					Err(ParseError::Incomplete) => {}, // The newline read with the line separates commands
+					parsed => break parsed,
+				},
+				Err(TokenizerState::BackSlashOutsideQuote | TokenizerState::BackSlashInDoubleQuote) => {
+					// A backslash-newline pair is removed entirely
+					input.truncate(input.trim_end_matches('\n').len() - 1);
+				},
+				Err(_) => {}, // Inside quotes or a here-document, the newline is kept
+			}
+
+			let ps2 = prompt::prompt_from("PS2", prompt::DEFAULT_PS2, &state);
+			let read = match editor.read_line(&ps2, &mut input, &state) {
+				Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+					state.last_status = 130;
+					continue 'prompt;
+				},
+				Err(e) => {
+					diagnostics::error(format_args!("read error: {e}"));
+					state.last_status = 1;
+					continue 'prompt;
+				},
+				Ok(read) => read,
+			};
+			if read == 0 {
+				break parse_to_end(input.trim(), &state.aliases);
+			}
+		};
+
+		// History expansion, e.g. `!!`, applies to the whole command; show the result
+		let parsed = match state.history.expand(&input) {
+			Ok(Some(expanded)) => {
+				input = expanded;
+				println!("{}", input.trim());
+				parse_to_end(input.trim(), &state.aliases)
+			},
+			Ok(None) => parsed,
+			Err(e) => {
+				diagnostics::error(e);
+				continue;
+			},
+		};
+
+		state.history.add(input.trim(), state.shopt.histappend);
+
+		let commands = match parsed {
+			Ok(p) => p,
+			Err(e) => {
+				diagnostics::error(e);
+				continue;
+			}
+		};
+
+		run_command_list(commands, &mut state)?;
     }
 }
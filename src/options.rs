@@ -0,0 +1,110 @@
+//! Shell options: those of `set`, turned on with `set -e` or `set -o errexit`
+//! and off again with `set +e` or `set +o errexit`, and those of `shopt`.
+
+/// The on/off options of the shell. `set -o vi` and `set -o emacs` pick the
+/// line editor's key bindings instead, which are kept with the editor.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+	pub errexit: bool, // -e: exit when a command fails, unless its status is being tested
+	pub nounset: bool, // -u: make expanding an unset variable an error
+	pub pipefail: bool, // A pipeline's status is that of the last of its commands to fail, not just of its last command
+	pub xtrace: bool, // -x: print each command, after expansion, before running it
+}
+
+/// The name of every option, with its single-letter flag if it has one.
+const OPTIONS: [(&str, Option<char>); 4] = [("errexit", Some('e')), ("nounset", Some('u')), ("pipefail", None), ("xtrace", Some('x'))];
+
+impl Options {
+	/// Whether the option called `name` is on, or None when there is no such option.
+	pub fn get(&self, name: &str) -> Option<bool> {
+		match name {
+			"errexit" => Some(self.errexit),
+			"nounset" => Some(self.nounset),
+			"pipefail" => Some(self.pipefail),
+			"xtrace" => Some(self.xtrace),
+			_ => None,
+		}
+	}
+
+	/// Turn the option called `name` on or off. Returns false when there is
+	/// no such option.
+	pub fn set(&mut self, name: &str, on: bool) -> bool {
+		let option = match name {
+			"errexit" => &mut self.errexit,
+			"nounset" => &mut self.nounset,
+			"pipefail" => &mut self.pipefail,
+			"xtrace" => &mut self.xtrace,
+			_ => return false,
+		};
+		*option = on;
+		true
+	}
+
+	/// The name of the option with the single-letter `flag`.
+	pub fn name_of_flag(flag: char) -> Option<&'static str> {
+		OPTIONS.iter().find(|(_, f)| *f == Some(flag)).map(|(name, _)| *name)
+	}
+
+	/// Every option with whether it is on, in name order.
+	pub fn list(&self) -> Vec<(&'static str, bool)> {
+		OPTIONS.iter().map(|(name, _)| (*name, self.get(name) == Some(true))).collect()
+	}
+
+	/// The flags of the options that are on, for `$-`.
+	pub fn flags(&self) -> String {
+		OPTIONS
+			.iter()
+			.filter(|(name, _)| self.get(name) == Some(true))
+			.filter_map(|(_, flag)| *flag)
+			.collect()
+	}
+}
+
+/// Options toggled with `shopt -s name` and `shopt -u name`, which have no
+/// single-letter flags.
+#[derive(Debug, Clone, Copy)]
+pub struct Shopt {
+	pub dotglob: bool, // Globs also match names starting with `.`
+	pub histappend: bool, // Append entries to the history file, rather than rewrite it
+	pub nocaseglob: bool, // Globs ignore case
+}
+
+impl Default for Shopt {
+	fn default() -> Self {
+		// The history file has always been appended to as commands are entered
+		Shopt { dotglob: false, histappend: true, nocaseglob: false }
+	}
+}
+
+/// The name of every `shopt` option.
+const SHOPT_NAMES: [&str; 3] = ["dotglob", "histappend", "nocaseglob"];
+
+impl Shopt {
+	/// Whether the option called `name` is on, or None when there is no such option.
+	pub fn get(&self, name: &str) -> Option<bool> {
+		match name {
+			"dotglob" => Some(self.dotglob),
+			"histappend" => Some(self.histappend),
+			"nocaseglob" => Some(self.nocaseglob),
+			_ => None,
+		}
+	}
+
+	/// Turn the option called `name` on or off. Returns false when there is
+	/// no such option.
+	pub fn set(&mut self, name: &str, on: bool) -> bool {
+		let option = match name {
+			"dotglob" => &mut self.dotglob,
+			"histappend" => &mut self.histappend,
+			"nocaseglob" => &mut self.nocaseglob,
+			_ => return false,
+		};
+		*option = on;
+		true
+	}
+
+	/// Every option with whether it is on, in name order.
+	pub fn list(&self) -> Vec<(&'static str, bool)> {
+		SHOPT_NAMES.iter().map(|name| (*name, self.get(name) == Some(true))).collect()
+	}
+}
@@ -0,0 +1,126 @@
+//! The index of commands in the PATH directories. A directory is only read
+//! when a lookup first gets to it, and read again once its modification time
+//! shows that entries were added or removed, so the prompt doesn't wait on
+//! slow directories and newly installed commands are still found. Whether a
+//! file is executable is checked as it is looked up, since `chmod` leaves the
+//! directory's modification time alone.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// The file names in one directory, as of its modification time.
+#[derive(Debug, Clone)]
+struct Listing {
+	modified: Option<SystemTime>, // None when it can't be trusted, so the next lookup reads it again
+	names: HashSet<String>,
+}
+
+/// How long ago a directory must have changed for a listing of it to be
+/// trusted; a change in the same clock tick as the reading wouldn't show.
+const SETTLE_TIME: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Default)]
+pub struct PathIndex {
+	dirs: Vec<String>, // In search order
+	listings: RefCell<HashMap<String, Listing>>, // Read as lookups need them, even through `&self`
+}
+
+/// Whether `path` is a regular file, or a link to one, with an execute bit set.
+pub fn is_executable(path: &Path) -> bool {
+	fs::metadata(path).is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+/// Whether the command name `name` is a path to a file rather than a name to
+/// look up, because it has a directory separator.
+pub fn is_path(name: &str) -> bool {
+	name.contains(std::path::is_separator)
+}
+
+impl PathIndex {
+	/// An index of `dirs`, none of which have been read yet.
+	pub fn new(dirs: Vec<String>) -> Self {
+		PathIndex { dirs, listings: RefCell::default() }
+	}
+
+	/// The directories searched, in order.
+	pub fn dirs(&self) -> &[String] {
+		&self.dirs
+	}
+
+	/// Search `dirs` from now on. Returns whether they differ from the
+	/// directories searched so far.
+	pub fn set_dirs(&mut self, dirs: Vec<String>) -> bool {
+		if dirs == self.dirs {
+			return false;
+		}
+		self.dirs = dirs;
+		true
+	}
+
+	/// Forget every listing, so each directory is read again when next needed.
+	pub fn clear(&mut self) {
+		self.listings.get_mut().clear();
+	}
+
+	/// Where the command `name` is: the first of the directories that has it.
+	pub fn find(&self, name: &str) -> Option<PathBuf> {
+		self.dirs.iter().find_map(|dir| self.file_in(dir, name))
+	}
+
+	/// Every command called `name`, in search order.
+	pub fn find_all(&self, name: &str) -> Vec<PathBuf> {
+		self.dirs.iter().filter_map(|dir| self.file_in(dir, name)).collect()
+	}
+
+	/// The names of the commands starting with `prefix`, for completion.
+	pub fn names(&self, prefix: &str) -> HashSet<String> {
+		self.refresh(self.dirs.iter().map(String::as_str));
+		let listings = self.listings.borrow();
+		self.dirs
+			.iter()
+			.flat_map(|dir| listings[dir].names.iter().filter(|name| name.starts_with(prefix)).map(move |name| (dir, name)))
+			.filter(|(dir, name)| is_executable(&Path::new(dir).join(name)))
+			.map(|(_, name)| name.clone())
+			.collect()
+	}
+
+	/// The command `name` in `dir`, if its listing has it and it is still executable.
+	fn file_in(&self, dir: &str, name: &str) -> Option<PathBuf> {
+		let path = Path::new(dir).join(name);
+		(self.with_listing(dir, |listing| listing.contains(name)) && is_executable(&path)).then_some(path)
+	}
+
+	/// Run `f` on the names in `dir`, reading it first if it hasn't been read
+	/// since it last changed.
+	fn with_listing<T>(&self, dir: &str, f: impl FnOnce(&HashSet<String>) -> T) -> T {
+		self.refresh([dir]);
+		f(&self.listings.borrow()[dir].names)
+	}
+
+	/// Read those of `dirs` that haven't been read since they last changed.
+	fn refresh<'a>(&self, dirs: impl IntoIterator<Item = &'a str>) {
+		let mut listings = self.listings.borrow_mut();
+		for dir in dirs {
+			let modified = fs::metadata(dir).and_then(|meta| meta.modified()).ok();
+			if matches!(listings.get(dir), Some(listing) if modified.is_some() && listing.modified == modified) {
+				continue;
+			}
+			let settled = modified.filter(|time| time.elapsed().is_ok_and(|age| age >= SETTLE_TIME));
+			listings.insert(dir.to_owned(), Listing { modified: settled, names: read_names(dir) });
+		}
+	}
+}
+
+/// The names of the entries in `dir`; nothing when it can't be read.
+fn read_names(dir: &str) -> HashSet<String> {
+	fs::read_dir(dir)
+		.into_iter()
+		.flatten()
+		.filter_map(Result::ok)
+		.filter_map(|entry| entry.file_name().into_string().ok())
+		.collect()
+}
@@ -0,0 +1,225 @@
+//! Formatting for the `printf` builtin: `%` conversions with flags, a width
+//! and a precision, and backslash escapes in the format. The format is reused
+//! until every argument has been converted.
+
+use std::slice::Iter;
+
+use crate::expand::{self, CharStream};
+
+/// What a format produced, along with complaints about arguments it couldn't
+/// use, like `abc` for `%d`. The text is still written when there are some.
+pub struct Formatted {
+	pub text: String,
+	pub errors: Vec<String>,
+}
+
+/// The flags, width and precision between a `%` and its conversion character.
+#[derive(Default)]
+struct Spec {
+	left: bool, // -: pad on the right
+	plus: bool, // +: sign positive numbers
+	space: bool, // ' ': a space in place of a plus sign
+	zero: bool, // 0: pad numbers with zeros
+	alternate: bool, // #: prefix octal with 0 and hex with 0x
+	width: usize,
+	precision: Option<usize>,
+}
+
+/// Format `args` according to `format`. Conversions without an argument left
+/// use an empty string or zero.
+pub fn format(format: &str, args: &[&str]) -> Formatted {
+	let mut formatted = Formatted { text: String::new(), errors: Vec::new() };
+	let mut args = args.iter();
+	loop {
+		let left = args.len();
+		if !format_once(format, &mut args, &mut formatted) {
+			break;
+		}
+		// A format without conversions would otherwise loop forever
+		if args.len() == 0 || args.len() == left {
+			break;
+		}
+	}
+	formatted
+}
+
+/// Go through the format once, taking arguments as conversions need them.
+/// Returns false when output must stop, on `\c` or a bad conversion.
+fn format_once(format: &str, args: &mut Iter<&str>, out: &mut Formatted) -> bool {
+	let mut chars = format.chars().peekable();
+	while chars.peek().is_some() {
+		let mut literal = String::new();
+		while let Some(ch) = chars.next_if(|c| *c != '%') {
+			literal.push(ch);
+		}
+		let (decoded, stopped) = expand::backslash_escapes(&literal, false);
+		out.text.push_str(&decoded);
+		if stopped {
+			return false;
+		}
+
+		if chars.next().is_none() {
+			break;
+		}
+		if chars.next_if_eq(&'%').is_some() {
+			out.text.push('%');
+		} else if !convert(&mut chars, args, out) {
+			return false;
+		}
+	}
+	true
+}
+
+/// Read one conversion after its `%` and append what it produces.
+fn convert(chars: &mut CharStream, args: &mut Iter<&str>, out: &mut Formatted) -> bool {
+	let mut spec = Spec::default();
+	while let Some(flag) = chars.next_if(|c| "-+ 0#".contains(*c)) {
+		match flag {
+			'-' => spec.left = true,
+			'+' => spec.plus = true,
+			' ' => spec.space = true,
+			'0' => spec.zero = true,
+			_ => spec.alternate = true,
+		}
+	}
+	if let Some(width) = count(chars, args, out) {
+		// A negative width from `*` pads on the right
+		spec.left |= width < 0;
+		spec.width = width.unsigned_abs() as usize;
+	}
+	if chars.next_if_eq(&'.').is_some() {
+		spec.precision = Some(count(chars, args, out).unwrap_or(0).max(0) as usize);
+	}
+
+	let Some(conversion) = chars.next() else {
+		out.errors.push("`%': missing format character".to_string());
+		return false;
+	};
+	let arg = match conversion {
+		's' | 'b' | 'c' | 'd' | 'i' | 'u' | 'o' | 'x' | 'X' => args.next().copied().unwrap_or(""),
+		_ => {
+			out.errors.push(format!("`{conversion}': invalid format character"));
+			return false;
+		},
+	};
+	let text = match conversion {
+		's' => truncate(arg, spec.precision),
+		'b' => {
+			let (decoded, stopped) = expand::backslash_escapes(arg, true);
+			out.text.push_str(&pad(truncate(&decoded, spec.precision), &spec));
+			return !stopped;
+		},
+		'c' => arg.chars().take(1).collect(),
+		_ => {
+			let value = integer(arg).unwrap_or_else(|e| {
+				out.errors.push(e);
+				0
+			});
+			number(value, conversion, &spec)
+		},
+	};
+	out.text.push_str(&pad(text, &spec));
+	true
+}
+
+/// A width or precision: digits, or `*` to take it from the next argument.
+fn count(chars: &mut CharStream, args: &mut Iter<&str>, out: &mut Formatted) -> Option<i64> {
+	if chars.next_if_eq(&'*').is_some() {
+		let arg = args.next().copied().unwrap_or("");
+		return Some(integer(arg).unwrap_or_else(|e| {
+			out.errors.push(e);
+			0
+		}));
+	}
+	let mut value = None;
+	while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+		value = Some(value.unwrap_or(0i64).saturating_mul(10).saturating_add(digit.to_digit(10).unwrap() as i64));
+	}
+	value
+}
+
+/// Parse an integer argument: decimal, octal with a leading 0, hex with 0x,
+/// or a leading quote for the code of the character after it.
+fn integer(arg: &str) -> Result<i64, String> {
+	if let Some(quoted) = arg.strip_prefix(['\'', '"']) {
+		return Ok(quoted.chars().next().map_or(0, |c| c as i64));
+	}
+	let trimmed = arg.trim();
+	if trimmed.is_empty() {
+		return Ok(0);
+	}
+	let (negative, digits) = match trimmed.strip_prefix('-') {
+		Some(rest) => (true, rest),
+		None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+	};
+	let parsed = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+		u64::from_str_radix(hex, 16)
+	} else if digits.len() > 1 && digits.starts_with('0') {
+		u64::from_str_radix(&digits[1..], 8)
+	} else {
+		digits.parse()
+	};
+	match parsed {
+		// Values past i64 wrap, like they do in C
+		Ok(n) if !digits.starts_with(['+', '-']) => Ok(if negative { (n as i64).wrapping_neg() } else { n as i64 }),
+		_ => Err(format!("{arg}: invalid number")),
+	}
+}
+
+/// Write `value` for an integer conversion, before padding to the width.
+fn number(value: i64, conversion: char, spec: &Spec) -> String {
+	let mut digits = match conversion {
+		'd' | 'i' => value.unsigned_abs().to_string(),
+		'o' => format!("{:o}", value as u64),
+		'x' => format!("{:x}", value as u64),
+		'X' => format!("{:X}", value as u64),
+		_ => (value as u64).to_string(),
+	};
+	let mut prefix = match conversion {
+		'd' | 'i' if value < 0 => "-",
+		'd' | 'i' if spec.plus => "+",
+		'd' | 'i' if spec.space => " ",
+		'x' if spec.alternate && value != 0 => "0x",
+		'X' if spec.alternate && value != 0 => "0X",
+		_ => "",
+	};
+	match spec.precision {
+		// A precision is the least number of digits, and zero of them can show 0
+		Some(0) if value == 0 => digits.clear(),
+		Some(precision) if digits.len() < precision => digits.insert_str(0, &"0".repeat(precision - digits.len())),
+		Some(_) => {},
+		None if spec.zero && !spec.left => {
+			let width = spec.width.saturating_sub(prefix.len());
+			if digits.len() < width {
+				digits.insert_str(0, &"0".repeat(width - digits.len()));
+			}
+		},
+		None => {},
+	}
+	if conversion == 'o' && spec.alternate && !digits.starts_with('0') {
+		prefix = "0";
+	}
+	format!("{prefix}{digits}")
+}
+
+/// The first `precision` characters of `text`, or all of them without one.
+fn truncate(text: &str, precision: Option<usize>) -> String {
+	match precision {
+		Some(precision) => text.chars().take(precision).collect(),
+		None => text.to_string(),
+	}
+}
+
+/// Pad `text` with spaces to the width, on the left unless `-` was given.
+fn pad(text: String, spec: &Spec) -> String {
+	let len = text.chars().count();
+	if len >= spec.width {
+		return text;
+	}
+	let padding = " ".repeat(spec.width - len);
+	if spec.left {
+		text + &padding
+	} else {
+		padding + &text
+	}
+}
@@ -0,0 +1,162 @@
+//! Prompt strings: PS1 and its backslash escapes, expanded before every prompt.
+
+use std::env;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::{sys, ShellState};
+
+/// The prompt used when PS1 is unset.
+pub const DEFAULT_PS1: &str = "$ ";
+
+/// The prompt for continuation lines (open quotes, a trailing backslash or
+/// here-document bodies) when PS2 is unset.
+pub const DEFAULT_PS2: &str = "> ";
+
+/// The prefix of commands shown by `set -x` when PS4 is unset.
+pub const DEFAULT_PS4: &str = "+ ";
+
+/// The expanded prompt held by `var`, or `default` when it is unset.
+pub fn prompt_from(var: &str, default: &str, shell: &ShellState) -> String {
+	expand_prompt(shell.vars.get(var).unwrap_or(default), shell)
+}
+
+/// Expand the backslash escapes of a prompt string:
+/// `\u` user, `\h` host up to the first `.`, `\H` full host, `\w` working
+/// directory with `$HOME` shown as `~`, `\W` its last component, `\$` `#` for
+/// root and `$` otherwise, `\t` the time as HH:MM:SS, `\n` newline, `\e`
+/// escape and `\\` backslash. `\g` shows the git branch, see `git_status`.
+/// `\[` and `\]`, which bash uses to mark non-printing text, are dropped:
+/// escape sequences are recognised anyway.
+pub fn expand_prompt(template: &str, shell: &ShellState) -> String {
+	let mut prompt = String::new();
+	let mut chars = template.chars();
+	while let Some(ch) = chars.next() {
+		if ch != '\\' {
+			prompt.push(ch);
+			continue;
+		}
+		match chars.next() {
+			Some('u') => prompt.push_str(shell.vars.get("USER").unwrap_or_default()),
+			Some('h') => prompt.push_str(sys::host_name().unwrap_or_default().split('.').next().unwrap_or_default()),
+			Some('H') => prompt.push_str(&sys::host_name().unwrap_or_default()),
+			Some('w') => prompt.push_str(&working_directory(shell)),
+			Some('W') => {
+				let dir = working_directory(shell);
+				prompt.push_str(if dir == "/" { "/" } else { dir.rsplit('/').next().unwrap_or_default() });
+			},
+			Some('$') => prompt.push(if sys::is_root() { '#' } else { '$' }),
+			Some('t') => {
+				let (hours, minutes, seconds) = sys::local_time();
+				prompt.push_str(&format!("{hours:02}:{minutes:02}:{seconds:02}"));
+			},
+			Some('g') => prompt.push_str(&git_status().unwrap_or_default()),
+			Some('n') => prompt.push('\n'),
+			Some('e') => prompt.push('\x1b'),
+			Some('[' | ']') => {},
+			Some('\\') => prompt.push('\\'),
+			Some(other) => {
+				// Unknown escapes are kept as they are, like bash does
+				prompt.push('\\');
+				prompt.push(other);
+			},
+			None => prompt.push('\\'),
+		}
+	}
+	prompt
+}
+
+/// The working directory with a leading `$HOME` abbreviated to `~`.
+fn working_directory(shell: &ShellState) -> String {
+	let Ok(cwd) = env::current_dir() else {
+		return String::new();
+	};
+	abbreviate_home(&cwd.to_string_lossy(), shell)
+}
+
+/// `path` with a leading `$HOME` abbreviated to `~`, as the prompt and `dirs` show it.
+pub fn abbreviate_home(path: &str, shell: &ShellState) -> String {
+	match shell.vars.get("HOME").filter(|home| !home.is_empty() && *home != "/") {
+		Some(home) if path == home => "~".to_owned(),
+		Some(home) => match path.strip_prefix(home).filter(|rest| rest.starts_with('/')) {
+			Some(rest) => format!("~{rest}"),
+			None => path.to_owned(),
+		},
+		None => path.to_owned(),
+	}
+}
+
+/// The current git branch (or abbreviated commit when detached), followed by
+/// `*` when tracked files were modified. None outside a repository.
+/// Everything is read from `.git` directly rather than by running git, so the
+/// dirty check only compares file sizes and mtimes with the index: staged
+/// changes and new untracked files don't count.
+fn git_status() -> Option<String> {
+	let (git_dir, work_tree) = find_repository()?;
+	let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+	let head = head.trim();
+	let mut status = match head.strip_prefix("ref: ") {
+		Some(reference) => reference.strip_prefix("refs/heads/").unwrap_or(reference).to_owned(),
+		None => head.chars().take(7).collect(),
+	};
+	if fs::read(git_dir.join("index")).is_ok_and(|index| index_is_dirty(&index, &work_tree)) {
+		status.push('*');
+	}
+	Some(status)
+}
+
+/// The git directory and work tree of the repository containing the working
+/// directory. A `.git` file, as in worktrees and submodules, points to a git
+/// directory elsewhere, so the work tree is the directory holding `.git`
+/// rather than the git directory's parent.
+fn find_repository() -> Option<(PathBuf, PathBuf)> {
+	let cwd = env::current_dir().ok()?;
+	for dir in cwd.ancestors() {
+		let dot_git = dir.join(".git");
+		if dot_git.is_dir() {
+			return Some((dot_git, dir.to_owned()));
+		}
+		if let Some(target) = fs::read_to_string(&dot_git).ok().and_then(|s| s.trim().strip_prefix("gitdir: ").map(str::to_owned)) {
+			return Some((dir.join(target), dir.to_owned()));
+		}
+	}
+	None
+}
+
+/// Whether any file listed in a git index (versions 2 and 3) differs in size
+/// or mtime from the one in the work tree, or is gone.
+fn index_is_dirty(index: &[u8], work_tree: &Path) -> bool {
+	let word = |at: usize| index.get(at..at + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+	if index.get(..4) != Some(b"DIRC") || !matches!(word(4), Some(2 | 3)) {
+		return false;
+	}
+
+	let mut at = 12;
+	for _ in 0..word(8).unwrap_or(0) {
+		// ctime, mtime, dev, ino, mode, uid, gid and size, then the hash and flags
+		let (Some(mtime), Some(mtime_ns), Some(mode), Some(size), Some(flags)) =
+			(word(at + 8), word(at + 12), word(at + 24), word(at + 36), index.get(at + 60..at + 62))
+		else {
+			return false;
+		};
+		let extended = flags[0] & 0x40 != 0;
+		let path_start = at + 62 + if extended { 2 } else { 0 };
+		let Some(path_len) = index.get(path_start..).and_then(|rest| rest.iter().position(|&b| b == 0)) else {
+			return false;
+		};
+		let path = String::from_utf8_lossy(&index[path_start..path_start + path_len]);
+		at = (path_start + path_len + 8 - at) / 8 * 8 + at; // Entries are NUL-padded to a multiple of 8
+
+		if mode & 0o170000 == 0o160000 {
+			continue; // A submodule, which has its own index
+		}
+		let Ok(meta) = fs::symlink_metadata(work_tree.join(&*path)) else {
+			return true;
+		};
+		if meta.size() as u32 != size || meta.mtime() as u32 != mtime || (mtime_ns != 0 && meta.mtime_nsec() as u32 != mtime_ns) {
+			return true;
+		}
+	}
+	false
+}
@@ -0,0 +1,88 @@
+//! The `read` builtin's input: one line read straight from an fd, then split
+//! on IFS into the values of the named variables.
+
+use std::io;
+use std::os::fd::RawFd;
+
+use crate::sys;
+
+/// Read a line from `fd` a byte at a time, so nothing past the newline is
+/// consumed for the commands that read after us. Unless `raw`, a backslash
+/// before the newline continues the line, and other backslashes are kept for
+/// `split_fields`. Returns the line without its newline, and whether there
+/// was one, which there isn't at end of input.
+pub fn read_line(fd: RawFd, raw: bool) -> io::Result<(String, bool)> {
+	let mut line = Vec::new();
+	let mut byte = [0u8];
+	let mut escaped = false;
+	let complete = loop {
+		if sys::read_fd(fd, &mut byte)? == 0 {
+			break false;
+		}
+		match byte[0] {
+			b'\n' if escaped => {
+				line.pop(); // The backslash
+				escaped = false;
+			},
+			b'\n' => break true,
+			b'\\' if !raw => {
+				line.push(b'\\');
+				escaped = !escaped;
+			},
+			other => {
+				line.push(other);
+				escaped = false;
+			},
+		}
+	};
+	Ok((String::from_utf8_lossy(&line).into_owned(), complete))
+}
+
+/// Split `line` into `count` values, one per variable. Runs of IFS whitespace
+/// separate values and are trimmed from both ends, as is whitespace around any
+/// other IFS character. The last value gets the rest of the line, separators
+/// and all. Unless `raw`, a backslash makes the character after it ordinary.
+pub fn split_fields(line: &str, count: usize, ifs: &str, raw: bool) -> Vec<String> {
+	// Each character, with whether a backslash quoted it
+	let mut chars = Vec::new();
+	let mut iter = line.chars();
+	while let Some(ch) = iter.next() {
+		match ch {
+			'\\' if !raw => chars.extend(iter.next().map(|c| (c, true))),
+			_ => chars.push((ch, false)),
+		}
+	}
+	let is_separator = |&(ch, quoted): &(char, bool)| !quoted && ifs.contains(ch);
+	let is_space = |c: &(char, bool)| is_separator(c) && c.0.is_whitespace();
+	let text = |chars: &[(char, bool)]| chars.iter().map(|(ch, _)| ch).collect::<String>();
+
+	let mut values = Vec::new();
+	let mut pos = chars.iter().take_while(|c| is_space(c)).count();
+	for i in 0..count {
+		if i + 1 == count {
+			let mut end = chars.len();
+			while end > pos && is_space(&chars[end - 1]) {
+				end -= 1;
+			}
+			values.push(text(&chars[pos..end]));
+			break;
+		}
+
+		let start = pos;
+		while pos < chars.len() && !is_separator(&chars[pos]) {
+			pos += 1;
+		}
+		values.push(text(&chars[start..pos]));
+		// Step over whitespace around at most one other separator
+		while pos < chars.len() && is_space(&chars[pos]) {
+			pos += 1;
+		}
+		if pos < chars.len() && is_separator(&chars[pos]) {
+			pos += 1;
+			while pos < chars.len() && is_space(&chars[pos]) {
+				pos += 1;
+			}
+		}
+	}
+	values
+}
@@ -0,0 +1,507 @@
+//! Thin wrappers over the handful of libc calls std doesn't expose.
+//! libc itself is already linked by std, so we only need the declarations.
+//! Those, the constants and the struct layouts are copied from glibc on
+//! x86_64 Linux, so other targets are refused rather than miscompiled.
+
+#[cfg(not(all(target_os = "linux", target_env = "gnu", target_arch = "x86_64")))]
+compile_error!("the libc declarations in sys.rs are only written for x86_64 Linux with glibc");
+
+use std::ffi::CString;
+use std::io;
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::os::raw::{c_char, c_int};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub type Pid = i32;
+
+pub const SIGHUP: c_int = 1;
+pub const SIGINT: c_int = 2;
+pub const SIGPIPE: c_int = 13;
+pub const SIGTERM: c_int = 15;
+pub const SIGCHLD: c_int = 17;
+pub const SIGCONT: c_int = 18;
+pub const SIGTSTP: c_int = 20;
+pub const SIGTTIN: c_int = 21;
+pub const SIGTTOU: c_int = 22;
+
+/// Signal names without their SIG prefix, numbered from 1 as on Linux.
+pub const SIGNAL_NAMES: [&str; 31] = [
+	"HUP", "INT", "QUIT", "ILL", "TRAP", "ABRT", "BUS", "FPE", "KILL", "USR1", "SEGV", "USR2", "PIPE", "ALRM", "TERM", "STKFLT",
+	"CHLD", "CONT", "STOP", "TSTP", "TTIN", "TTOU", "URG", "XCPU", "XFSZ", "VTALRM", "PROF", "WINCH", "IO", "PWR", "SYS",
+];
+/// What each signal means, as bash words it when one kills a command.
+const SIGNAL_DESCRIPTIONS: [&str; 31] = [
+	"Hangup", "Interrupt", "Quit", "Illegal instruction", "Trace/breakpoint trap", "Aborted", "Bus error",
+	"Floating point exception", "Killed", "User defined signal 1", "Segmentation fault", "User defined signal 2",
+	"Broken pipe", "Alarm clock", "Terminated", "Stack fault", "Child exited", "Continued", "Stopped (signal)",
+	"Stopped", "Stopped (tty input)", "Stopped (tty output)", "Urgent I/O condition", "CPU time limit exceeded",
+	"File size limit exceeded", "Virtual timer expired", "Profiling timer expired", "Window changed", "I/O possible",
+	"Power failure", "Bad system call",
+];
+const WNOHANG: c_int = 1;
+const WUNTRACED: c_int = 2;
+const SIG_DFL: usize = 0;
+const SIG_IGN: usize = 1;
+const SIG_ERR: usize = usize::MAX;
+
+const F_SETFD: c_int = 2;
+const F_DUPFD_CLOEXEC: c_int = 1030;
+const O_CLOEXEC: c_int = 0o2000000;
+
+// termios flags, from <asm-generic/termbits.h>
+const ICRNL: u32 = 0o400;
+const IXON: u32 = 0o2000;
+const ISIG: u32 = 0o1;
+const ICANON: u32 = 0o2;
+const ECHO: u32 = 0o10;
+const IEXTEN: u32 = 0o100000;
+const VTIME: usize = 5;
+const VMIN: usize = 6;
+const TCSADRAIN: c_int = 1;
+const TIOCGWINSZ: u64 = 0x5413;
+const POLLIN: i16 = 1;
+
+const REG_EXTENDED: c_int = 1;
+const REG_NOMATCH: c_int = 1;
+const MAX_GROUPS: usize = 32;
+
+// Modes for `can_access`
+pub const R_OK: c_int = 4;
+pub const W_OK: c_int = 2;
+pub const X_OK: c_int = 1;
+
+#[repr(C)]
+struct PollFd {
+	fd: c_int,
+	events: i16,
+	revents: i16,
+}
+
+/// Broken-down time, laid out like glibc's `struct tm`.
+#[repr(C)]
+struct Tm {
+	tm_sec: c_int,
+	tm_min: c_int,
+	tm_hour: c_int,
+	tm_mday: c_int,
+	tm_mon: c_int,
+	tm_year: c_int,
+	tm_wday: c_int,
+	tm_yday: c_int,
+	tm_isdst: c_int,
+	tm_gmtoff: i64,
+	tm_zone: *const u8,
+}
+
+/// A compiled regular expression, laid out like glibc's `regex_t`.
+#[repr(C)]
+struct RegexT {
+	buffer: *mut u8,
+	allocated: usize,
+	used: usize,
+	syntax: u64,
+	fastmap: *mut u8,
+	translate: *mut u8,
+	re_nsub: usize, // Number of parenthesised groups
+	flags: u64,
+}
+
+/// Where a regex group matched, laid out like glibc's `regmatch_t`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RegMatch {
+	rm_so: c_int, // Byte offsets of the start and end, or -1 when the group took no part
+	rm_eo: c_int,
+}
+
+/// Terminal attributes, laid out like glibc's `struct termios`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Termios {
+	c_iflag: u32,
+	c_oflag: u32,
+	c_cflag: u32,
+	c_lflag: u32,
+	c_line: u8,
+	c_cc: [u8; 32],
+	c_ispeed: u32,
+	c_ospeed: u32,
+}
+
+extern "C" {
+	fn dup2(oldfd: c_int, newfd: c_int) -> c_int;
+	fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+	fn close(fd: c_int) -> c_int;
+	fn fork() -> Pid;
+	fn pipe2(fds: *mut c_int, flags: c_int) -> c_int;
+	fn waitpid(pid: Pid, status: *mut c_int, options: c_int) -> Pid;
+	fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+	fn tcsetattr(fd: c_int, actions: c_int, termios: *const Termios) -> c_int;
+	fn ioctl(fd: c_int, request: u64, ...) -> c_int;
+	fn read(fd: c_int, buf: *mut u8, count: usize) -> isize;
+	fn poll(fds: *mut PollFd, nfds: u64, timeout: c_int) -> c_int;
+	fn signal(signum: c_int, handler: usize) -> usize;
+	fn siginterrupt(signum: c_int, flag: c_int) -> c_int;
+	fn gethostname(name: *mut u8, len: usize) -> c_int;
+	fn geteuid() -> u32;
+	fn getegid() -> u32;
+	fn access(path: *const c_char, mode: c_int) -> c_int;
+	fn kill(pid: Pid, sig: c_int) -> c_int;
+	fn setpgid(pid: Pid, pgid: Pid) -> c_int;
+	fn getpgrp() -> Pid;
+	fn tcsetpgrp(fd: c_int, pgrp: Pid) -> c_int;
+	fn tcgetpgrp(fd: c_int) -> Pid;
+	fn isatty(fd: c_int) -> c_int;
+	fn regcomp(preg: *mut RegexT, regex: *const c_char, cflags: c_int) -> c_int;
+	fn regexec(preg: *const RegexT, string: *const c_char, nmatch: usize, pmatch: *mut RegMatch, eflags: c_int) -> c_int;
+	fn regerror(errcode: c_int, preg: *const RegexT, errbuf: *mut u8, errbuf_size: usize) -> usize;
+	fn regfree(preg: *mut RegexT);
+	fn time(t: *mut i64) -> i64;
+	fn localtime_r(t: *const i64, tm: *mut Tm) -> *mut Tm;
+}
+
+/// Convert a libc return value into an io::Result, reading errno on failure.
+fn cvt(ret: c_int) -> io::Result<c_int> {
+	if ret < 0 { Err(io::Error::last_os_error()) } else { Ok(ret) }
+}
+
+/// Make `newfd` refer to the same open file as `oldfd`.
+/// Only async-signal-safe work happens here, so it is usable from `pre_exec`.
+pub fn dup_onto(oldfd: RawFd, newfd: RawFd) -> io::Result<()> {
+	if oldfd == newfd {
+		// dup2 is a no-op here, but the fd must still survive exec
+		return set_inheritable(newfd);
+	}
+	cvt(unsafe { dup2(oldfd, newfd) }).map(|_| ())
+}
+
+/// Close an fd owned elsewhere, e.g. a copy a forked child inherited but
+/// must not hold on to.
+pub fn close_fd(fd: RawFd) -> io::Result<()> {
+	cvt(unsafe { close(fd) }).map(|_| ())
+}
+
+/// Clear close-on-exec so a command we spawn inherits the fd.
+pub fn set_inheritable(fd: RawFd) -> io::Result<()> {
+	cvt(unsafe { fcntl(fd, F_SETFD, 0) }).map(|_| ())
+}
+
+/// Duplicate an fd owned by someone else (e.g. one the shell inherited),
+/// failing with EBADF instead of misbehaving when it isn't open.
+pub fn dup_fd(fd: RawFd) -> io::Result<OwnedFd> {
+	let new = cvt(unsafe { fcntl(fd, F_DUPFD_CLOEXEC, 0) })?;
+	Ok(unsafe { OwnedFd::from_raw_fd(new) })
+}
+
+/// Create a pipe, returning (read end, write end). Both ends are close-on-exec;
+/// whichever end a child needs is dup'ed onto a standard fd, which clears that.
+pub fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+	let mut fds = [0; 2];
+	cvt(unsafe { pipe2(fds.as_mut_ptr(), O_CLOEXEC) })?;
+	Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+/// Fork the shell. Returns 0 in the child and the child's pid in the parent.
+/// The shell is single-threaded, so the child may keep running ordinary Rust code.
+pub fn fork_process() -> io::Result<Pid> {
+	cvt(unsafe { fork() })
+}
+
+/// Block until the given child exits.
+pub fn wait_for(pid: Pid) -> io::Result<ExitStatus> {
+	wait_with_options(pid, 0)
+}
+
+/// Block until the given child exits or is stopped, e.g. by Ctrl-Z; check
+/// `stopped_signal()` on the result to tell which.
+pub fn wait_for_exit_or_stop(pid: Pid) -> io::Result<ExitStatus> {
+	wait_with_options(pid, WUNTRACED)
+}
+
+/// Whether the given child has exited or been stopped, without blocking:
+/// its status if so, None while it is still running.
+pub fn poll_child(pid: Pid) -> io::Result<Option<ExitStatus>> {
+	let mut status = 0;
+	match cvt(unsafe { waitpid(pid, &mut status, WNOHANG | WUNTRACED) })? {
+		0 => Ok(None),
+		_ => Ok(Some(ExitStatus::from_raw(status))),
+	}
+}
+
+fn wait_with_options(pid: Pid, options: c_int) -> io::Result<ExitStatus> {
+	let mut status = 0;
+	loop {
+		match cvt(unsafe { waitpid(pid, &mut status, options) }) {
+			Ok(_) => return Ok(ExitStatus::from_raw(status)),
+			Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+			Err(e) => return Err(e),
+		}
+	}
+}
+
+/// Switch the terminal on `fd` to raw mode: bytes arrive one at a time,
+/// unechoed, with no line editing by the kernel, and keys like Ctrl-C arrive
+/// as bytes instead of raising signals. Output processing stays on.
+/// Returns the previous mode, for `set_terminal_mode`.
+pub fn enable_raw_mode(fd: RawFd) -> io::Result<Termios> {
+	let original = terminal_mode(fd)?;
+
+	let mut raw = original;
+	raw.c_iflag &= !(ICRNL | IXON);
+	raw.c_lflag &= !(ISIG | ICANON | ECHO | IEXTEN);
+	raw.c_cc[VMIN] = 1;
+	raw.c_cc[VTIME] = 0;
+	set_terminal_mode(fd, &raw)?;
+	Ok(original)
+}
+
+/// The current attributes of the terminal on `fd`, for `set_terminal_mode`.
+pub fn terminal_mode(fd: RawFd) -> io::Result<Termios> {
+	let mut termios = std::mem::MaybeUninit::<Termios>::uninit();
+	cvt(unsafe { tcgetattr(fd, termios.as_mut_ptr()) })?;
+	Ok(unsafe { termios.assume_init() })
+}
+
+/// Apply terminal attributes, e.g. to restore those saved by `enable_raw_mode`.
+pub fn set_terminal_mode(fd: RawFd, termios: &Termios) -> io::Result<()> {
+	cvt(unsafe { tcsetattr(fd, TCSADRAIN, termios) }).map(|_| ())
+}
+
+/// Number of columns of the terminal on `fd`.
+pub fn terminal_width(fd: RawFd) -> io::Result<usize> {
+	// struct winsize: rows, columns, then pixel sizes
+	let mut size = [0u16; 4];
+	cvt(unsafe { ioctl(fd, TIOCGWINSZ, size.as_mut_ptr()) })?;
+	Ok(size[1] as usize)
+}
+
+/// Read from an fd owned elsewhere, bypassing std's buffered stdin so that
+/// `wait_readable` sees exactly what hasn't been consumed yet.
+pub fn read_fd(fd: RawFd, buf: &mut [u8]) -> io::Result<usize> {
+	read_retrying(fd, buf, || true)
+}
+
+/// Like `read_fd`, but give up with an `Interrupted` error once a signal
+/// caught by `catch_signal` is waiting to be handled.
+pub fn read_fd_until_signal(fd: RawFd, buf: &mut [u8]) -> io::Result<usize> {
+	read_retrying(fd, buf, || !signals_pending())
+}
+
+fn read_retrying(fd: RawFd, buf: &mut [u8], retry: impl Fn() -> bool) -> io::Result<usize> {
+	loop {
+		let ret = unsafe { read(fd, buf.as_mut_ptr(), buf.len()) };
+		if ret >= 0 {
+			return Ok(ret as usize);
+		}
+		let err = io::Error::last_os_error();
+		if err.kind() != io::ErrorKind::Interrupted || !retry() {
+			return Err(err);
+		}
+	}
+}
+
+/// Wait up to `timeout_ms` for `fd` to have input, returning whether it does.
+pub fn wait_readable(fd: RawFd, timeout_ms: i32) -> io::Result<bool> {
+	let mut pollfd = PollFd { fd, events: POLLIN, revents: 0 };
+	Ok(cvt(unsafe { poll(&mut pollfd, 1, timeout_ms) })? > 0)
+}
+
+extern "C" fn ignore_signal(_signal: c_int) {}
+
+/// Signals caught by `record_signal` that haven't been handled yet, one bit each.
+static PENDING_SIGNALS: AtomicU64 = AtomicU64::new(0);
+
+extern "C" fn record_signal(signal_number: c_int) {
+	PENDING_SIGNALS.fetch_or(1 << signal_number, Ordering::SeqCst);
+}
+
+fn set_handler(signal_number: c_int, handler: usize) -> io::Result<()> {
+	match unsafe { signal(signal_number, handler) } {
+		SIG_ERR => Err(io::Error::last_os_error()),
+		_ => Ok(()),
+	}
+}
+
+/// Make the shell survive `signal` by catching it and doing nothing. Unlike
+/// ignoring it outright, a caught signal is reset to its default action on
+/// exec, so the commands we run can still be interrupted.
+pub fn survive_signal(signal_number: c_int) -> io::Result<()> {
+	set_handler(signal_number, ignore_signal as extern "C" fn(c_int) as usize)
+}
+
+/// Catch `signal_number`, noting its arrival for `take_pending_signals`.
+/// Reads it arrives during fail rather than restart, so a shell waiting for
+/// input gets to handle it; SIGCHLD, which comes whenever a job finishes,
+/// leaves them alone.
+pub fn catch_signal(signal_number: c_int) -> io::Result<()> {
+	set_handler(signal_number, record_signal as extern "C" fn(c_int) as usize)?;
+	let interrupt = c_int::from(signal_number != SIGCHLD);
+	cvt(unsafe { siginterrupt(signal_number, interrupt) }).map(drop)
+}
+
+/// Ignore `signal_number`, in the commands we run too.
+pub fn ignore_signal_entirely(signal_number: c_int) -> io::Result<()> {
+	set_handler(signal_number, SIG_IGN)
+}
+
+/// Give `signal_number` back its default action.
+pub fn reset_signal(signal_number: c_int) -> io::Result<()> {
+	set_handler(signal_number, SIG_DFL)
+}
+
+/// Whether a signal caught by `catch_signal`, other than SIGCHLD, is
+/// waiting to be handled.
+pub fn signals_pending() -> bool {
+	PENDING_SIGNALS.load(Ordering::SeqCst) & !(1 << SIGCHLD) != 0
+}
+
+/// The signals caught since the last call, lowest number first.
+pub fn take_pending_signals() -> Vec<c_int> {
+	let pending = PENDING_SIGNALS.swap(0, Ordering::SeqCst);
+	(1..64).filter(|n| pending & (1 << n) != 0).collect()
+}
+
+/// The machine's host name.
+pub fn host_name() -> io::Result<String> {
+	let mut buf = [0u8; 256];
+	cvt(unsafe { gethostname(buf.as_mut_ptr(), buf.len()) })?;
+	let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+	Ok(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+/// Whether the shell runs as root.
+pub fn is_root() -> bool {
+	unsafe { geteuid() == 0 }
+}
+
+/// The effective user and group ids the shell runs as.
+pub fn effective_ids() -> (u32, u32) {
+	unsafe { (geteuid(), getegid()) }
+}
+
+/// Whether the shell may use `path` in `mode`, a mix of `R_OK`, `W_OK` and `X_OK`.
+pub fn can_access(path: &Path, mode: c_int) -> bool {
+	let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+		return false;
+	};
+	unsafe { access(path.as_ptr(), mode) == 0 }
+}
+
+/// Send `signal_number` to the process `pid`; 0 just checks that it exists.
+pub fn send_signal(pid: Pid, signal_number: c_int) -> io::Result<()> {
+	cvt(unsafe { kill(pid, signal_number) }).map(drop)
+}
+
+/// Send `signal_number` to every process in the process group `pgid`.
+pub fn signal_group(pgid: Pid, signal_number: c_int) -> io::Result<()> {
+	cvt(unsafe { kill(-pgid, signal_number) }).map(drop)
+}
+
+/// Move the process `pid` into the process group `pgid`; 0 for either means
+/// the calling process, so `(0, 0)` starts a group of its own. Usable from
+/// `pre_exec`.
+pub fn set_process_group(pid: Pid, pgid: Pid) -> io::Result<()> {
+	cvt(unsafe { setpgid(pid, pgid) }).map(drop)
+}
+
+/// The process group of the calling process.
+pub fn process_group() -> Pid {
+	unsafe { getpgrp() }
+}
+
+/// Make `pgid` the foreground process group of the terminal on `fd`: the
+/// one that may read from it and gets the signals its keys raise, like
+/// Ctrl-C's SIGINT. Usable from `pre_exec`.
+pub fn set_foreground_group(fd: RawFd, pgid: Pid) -> io::Result<()> {
+	cvt(unsafe { tcsetpgrp(fd, pgid) }).map(drop)
+}
+
+/// The foreground process group of the terminal on `fd`.
+pub fn foreground_group(fd: RawFd) -> io::Result<Pid> {
+	cvt(unsafe { tcgetpgrp(fd) })
+}
+
+/// The number of a signal given by number or by name, with or without its
+/// SIG prefix and in any case, e.g. `9`, `KILL` or `sigkill`.
+pub fn signal_number(spec: &str) -> Option<c_int> {
+	if let Ok(number) = spec.parse::<c_int>() {
+		return Some(number).filter(|n| (0..=SIGNAL_NAMES.len() as c_int).contains(n));
+	}
+	let upper = spec.to_ascii_uppercase();
+	let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+	SIGNAL_NAMES.iter().position(|known| *known == name).map(|i| i as c_int + 1)
+}
+
+/// The name of signal `signal_number` without its SIG prefix.
+pub fn signal_name(signal_number: c_int) -> Option<&'static str> {
+	usize::try_from(signal_number).ok()?.checked_sub(1).and_then(|i| SIGNAL_NAMES.get(i)).copied()
+}
+
+/// How a process killed by a signal ended, e.g. `Segmentation fault (core
+/// dumped)`; None when no signal killed it.
+pub fn termination_message(status: ExitStatus) -> Option<String> {
+	let signal_number = status.signal()?;
+	let description = usize::try_from(signal_number - 1).ok().and_then(|i| SIGNAL_DESCRIPTIONS.get(i));
+	let mut message = match description {
+		Some(description) => description.to_string(),
+		None => format!("Unknown signal {signal_number}"),
+	};
+	if status.core_dumped() {
+		message.push_str(" (core dumped)");
+	}
+	Some(message)
+}
+
+/// Whether `fd` is open and refers to a terminal.
+pub fn is_terminal(fd: RawFd) -> bool {
+	unsafe { isatty(fd) == 1 }
+}
+
+/// Search `text` for the POSIX extended regular expression `pattern`. On a
+/// match, returns the matched text followed by that of each group, empty for
+/// groups that took no part. Errors are those of an invalid pattern.
+pub fn regex_match(pattern: &str, text: &str) -> Result<Option<Vec<String>>, String> {
+	let pattern = CString::new(pattern).map_err(|e| e.to_string())?;
+	let text = CString::new(text).map_err(|e| e.to_string())?;
+	let mut regex = std::mem::MaybeUninit::<RegexT>::uninit();
+	let code = unsafe { regcomp(regex.as_mut_ptr(), pattern.as_ptr(), REG_EXTENDED) };
+	if code != 0 {
+		let mut buf = [0u8; 256];
+		let len = unsafe { regerror(code, regex.as_ptr(), buf.as_mut_ptr(), buf.len()) };
+		let len = buf[..len.min(buf.len())].iter().position(|&b| b == 0).unwrap_or(len);
+		return Err(String::from_utf8_lossy(&buf[..len]).into_owned());
+	}
+	let mut regex = unsafe { regex.assume_init() };
+
+	let mut groups = [RegMatch { rm_so: -1, rm_eo: -1 }; MAX_GROUPS];
+	let code = unsafe { regexec(&regex, text.as_ptr(), groups.len(), groups.as_mut_ptr(), 0) };
+	let count = (regex.re_nsub + 1).min(MAX_GROUPS);
+	unsafe { regfree(&mut regex) };
+	if code == REG_NOMATCH {
+		return Ok(None);
+	}
+
+	let bytes = text.as_bytes();
+	Ok(Some(groups[..count]
+		.iter()
+		.map(|group| match (usize::try_from(group.rm_so), usize::try_from(group.rm_eo)) {
+			(Ok(start), Ok(end)) => String::from_utf8_lossy(&bytes[start..end]).into_owned(),
+			_ => String::new(),
+		})
+		.collect()))
+}
+
+/// The current local time of day as hours, minutes and seconds.
+pub fn local_time() -> (u32, u32, u32) {
+	let now = unsafe { time(std::ptr::null_mut()) };
+	let mut tm = Tm {
+		tm_sec: 0, tm_min: 0, tm_hour: 0, tm_mday: 0, tm_mon: 0, tm_year: 0,
+		tm_wday: 0, tm_yday: 0, tm_isdst: 0, tm_gmtoff: 0, tm_zone: std::ptr::null(),
+	};
+	unsafe { localtime_r(&now, &mut tm) };
+	(tm.tm_hour as u32, tm.tm_min as u32, tm.tm_sec as u32)
+}
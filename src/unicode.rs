@@ -0,0 +1,147 @@
+//! Just enough Unicode for the line editor: where grapheme clusters (what the
+//! user sees as one character) begin, and how many terminal columns a
+//! character takes. The tables cover combining marks, emoji sequences and
+//! East Asian wide characters, not every corner of the standard.
+
+const ZERO_WIDTH_JOINER: char = '\u{200d}';
+
+/// Marks that combine with the character before them instead of standing
+/// alone: combining accents, variation selectors, emoji skin tones and tags.
+fn is_extending(ch: char) -> bool {
+	matches!(ch as u32,
+		0x0300..=0x036f // Combining diacritical marks
+		| 0x0483..=0x0489
+		| 0x0591..=0x05bd
+		| 0x0610..=0x061a
+		| 0x064b..=0x065f
+		| 0x0900..=0x0903 | 0x093a..=0x094f // Devanagari signs
+		| 0x0e31 | 0x0e34..=0x0e3a | 0x0e47..=0x0e4e // Thai vowels and tones
+		| 0x1ab0..=0x1aff
+		| 0x1dc0..=0x1dff
+		| 0x200c..=0x200d // Zero width (non-)joiner
+		| 0x20d0..=0x20ff
+		| 0x3099..=0x309a // Kana voicing marks
+		| 0xfe00..=0xfe0f // Variation selectors
+		| 0xfe20..=0xfe2f
+		| 0x1f3fb..=0x1f3ff // Emoji skin tone modifiers
+		| 0xe0020..=0xe007f // Tags, as in subdivision flags
+		| 0xe0100..=0xe01ef
+	)
+}
+
+fn is_regional_indicator(ch: char) -> bool {
+	matches!(ch as u32, 0x1f1e6..=0x1f1ff)
+}
+
+/// Whether a terminal shows `ch` two columns wide.
+fn is_wide(ch: char) -> bool {
+	matches!(ch as u32,
+		0x1100..=0x115f // Hangul Jamo
+		| 0x231a..=0x231b | 0x23e9..=0x23ec | 0x23f0 | 0x23f3
+		| 0x25fd..=0x25fe | 0x2614..=0x2615 | 0x2648..=0x2653
+		| 0x26aa..=0x26ab | 0x26bd..=0x26be | 0x26c4..=0x26c5 | 0x26ce | 0x26d4 | 0x26ea
+		| 0x26f2..=0x26f5 | 0x26fa | 0x26fd | 0x2705 | 0x270a..=0x270b | 0x2728 | 0x274c
+		| 0x2753..=0x2755 | 0x2757 | 0x2795..=0x2797 | 0x27b0 | 0x27bf | 0x2b1b..=0x2b1c
+		| 0x2b50 | 0x2b55
+		| 0x2e80..=0x303e // CJK radicals and punctuation
+		| 0x3041..=0x33ff // Kana and CJK compatibility
+		| 0x3400..=0x4dbf // CJK extension A
+		| 0x4e00..=0x9fff // CJK unified ideographs
+		| 0xa000..=0xa4cf // Yi
+		| 0xa960..=0xa97f
+		| 0xac00..=0xd7a3 // Hangul syllables
+		| 0xf900..=0xfaff
+		| 0xfe10..=0xfe19 | 0xfe30..=0xfe6f
+		| 0xff00..=0xff60 | 0xffe0..=0xffe6 // Fullwidth forms
+		| 0x16fe0..=0x16fe4 | 0x17000..=0x18cff | 0x1b000..=0x1b2ff
+		| 0x1f004 | 0x1f0cf | 0x1f18e | 0x1f191..=0x1f19a
+		| 0x1f1e6..=0x1f1ff // Regional indicators, shown as flags in pairs
+		| 0x1f200..=0x1f251
+		| 0x1f300..=0x1f64f // Pictographs and emoticons
+		| 0x1f680..=0x1f6ff
+		| 0x1f7e0..=0x1f7eb
+		| 0x1f90c..=0x1f9ff
+		| 0x1fa70..=0x1faff
+		| 0x20000..=0x3fffd // CJK extensions B and beyond
+	)
+}
+
+/// Columns `ch` takes in a terminal: 0 for marks that combine with the
+/// previous character, 2 for wide characters, 1 otherwise.
+pub fn char_width(ch: char) -> usize {
+	if is_extending(ch) || ch.is_control() {
+		0
+	} else if is_wide(ch) {
+		2
+	} else {
+		1
+	}
+}
+
+/// Columns a string takes in a terminal.
+pub fn str_width(text: &str) -> usize {
+	text.chars().map(char_width).sum()
+}
+
+/// Columns a string takes in a terminal once its escape sequences (colours
+/// or a window title, e.g. from a prompt) are interpreted rather than shown.
+pub fn visible_width(text: &str) -> usize {
+	let mut width = 0;
+	let mut chars = text.chars();
+	while let Some(ch) = chars.next() {
+		if ch != '\x1b' {
+			width += char_width(ch);
+			continue;
+		}
+		match chars.next() {
+			// CSI, e.g. colours: parameters up to a final letter
+			Some('[') => for c in chars.by_ref() {
+				if ('@'..='~').contains(&c) {
+					break;
+				}
+			},
+			// OSC, e.g. the window title: up to BEL or ESC \
+			Some(']') => for c in chars.by_ref() {
+				if c == '\x07' || c == '\x1b' {
+					break;
+				}
+			},
+			_ => {},
+		}
+	}
+	width
+}
+
+/// Columns a run of chars takes in a terminal.
+pub fn chars_width(chars: &[char]) -> usize {
+	chars.iter().copied().map(char_width).sum()
+}
+
+/// Whether a grapheme cluster boundary falls between `chars[i - 1]` and
+/// `chars[i]`. The ends of the text are always boundaries.
+pub fn is_boundary(chars: &[char], i: usize) -> bool {
+	if i == 0 || i >= chars.len() {
+		return true;
+	}
+	let (before, after) = (chars[i - 1], chars[i]);
+	if is_extending(after) || before == ZERO_WIDTH_JOINER {
+		return false;
+	}
+	if is_regional_indicator(before) && is_regional_indicator(after) {
+		// Flags are pairs of regional indicators: odd-length runs before `i`
+		// mean `before` is the first half of one
+		let run = chars[..i].iter().rev().take_while(|&&c| is_regional_indicator(c)).count();
+		return run % 2 == 0;
+	}
+	true
+}
+
+/// The boundary before `i`, i.e. where the grapheme ending at `i` starts.
+pub fn prev_boundary(chars: &[char], i: usize) -> usize {
+	(0..i).rev().find(|&j| is_boundary(chars, j)).unwrap_or(0)
+}
+
+/// The boundary after `i`, i.e. where the grapheme starting at `i` ends.
+pub fn next_boundary(chars: &[char], i: usize) -> usize {
+	(i + 1..=chars.len()).find(|&j| is_boundary(chars, j)).unwrap_or(chars.len())
+}
@@ -0,0 +1,83 @@
+//! Shell variables, kept separately from the process environment.
+//! Only variables marked for export are mirrored into `std::env`, which is
+//! what spawned children inherit.
+
+use std::collections::HashMap;
+use std::env;
+
+#[derive(Debug, Clone)]
+struct Variable {
+	value: Option<String>, // None for `export NAME` before NAME is ever set
+	exported: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Variables {
+	table: HashMap<String, Variable>,
+}
+
+/// Whether `name` can be used as a variable name, e.g. `_foo1` but not `1foo`.
+pub fn is_valid_name(name: &str) -> bool {
+	let mut chars = name.chars();
+	matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+		&& chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl Variables {
+	/// Start with every inherited environment variable, all exported.
+	pub fn from_env() -> Self {
+		let table = env::vars()
+			.map(|(name, value)| (name, Variable { value: Some(value), exported: true }))
+			.collect();
+		Variables { table }
+	}
+
+	pub fn get(&self, name: &str) -> Option<&str> {
+		self.table.get(name).and_then(|v| v.value.as_deref())
+	}
+
+	/// Set a variable, keeping its export flag (and the environment) in sync.
+	pub fn set(&mut self, name: &str, value: &str) {
+		let var = self.table
+			.entry(name.to_owned())
+			.or_insert(Variable { value: None, exported: false });
+		var.value = Some(value.to_owned());
+		if var.exported {
+			env::set_var(name, value);
+		}
+	}
+
+	/// Mark a variable for export, optionally assigning it at the same time.
+	pub fn export(&mut self, name: &str, value: Option<&str>) {
+		let var = self.table
+			.entry(name.to_owned())
+			.or_insert(Variable { value: None, exported: false });
+		var.exported = true;
+		if let Some(value) = value {
+			var.value = Some(value.to_owned());
+		}
+		if let Some(value) = &var.value {
+			env::set_var(name, value);
+		}
+	}
+
+	/// Remove a variable from the shell and, if it was exported, the environment.
+	pub fn unset(&mut self, name: &str) {
+		if let Some(var) = self.table.remove(name) {
+			if var.exported {
+				env::remove_var(name);
+			}
+		}
+	}
+
+	/// Exported variables sorted by name, as listed by `export -p`.
+	pub fn exported(&self) -> Vec<(&str, Option<&str>)> {
+		let mut list: Vec<_> = self.table
+			.iter()
+			.filter(|(_, v)| v.exported)
+			.map(|(name, v)| (name.as_str(), v.value.as_deref()))
+			.collect();
+		list.sort();
+		list
+	}
+}